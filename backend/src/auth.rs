@@ -0,0 +1,190 @@
+use std::borrow::Cow;
+
+use axum::{http::{header, HeaderValue, Request, StatusCode, Uri}, middleware::Next, response::Response};
+use serde::Deserialize;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tracing::{warn, Span};
+
+use crate::config::config;
+
+/// What an api token is allowed to do, operators can do everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiScope {
+    /// Turtle list, worlds, inventories and events
+    Read,
+    /// Everything that makes the turtle do something (including running lua)
+    Operator,
+}
+
+/// `[auth]` section of the config, without any tokens the api is open
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Sent by the turtle in its handshake
+    turtle_secret: Option<String>,
+    read_tokens: Vec<String>,
+    operator_tokens: Vec<String>,
+    /// Any origin is allowed when it is empty
+    cors_origins: Vec<String>,
+}
+
+/// Compares the whole secret so the time it takes does not tell how much of it was right
+fn secret_eq(left: &str, right: &str) -> bool {
+    left.len() == right.len() && left.bytes().zip(right.bytes()).fold(0, |acc, (l, r)| acc | (l ^ r)) == 0
+}
+
+impl AuthConfig {
+    pub fn warn_if_open(&self) {
+        if self.turtle_secret().is_none() {
+            warn!("auth.turtle_secret is not set, anyone can connect as a turtle");
+        }
+        if self.read_tokens.is_empty() && self.operator_tokens.is_empty() {
+            warn!("No api tokens are set, anyone can control the turtles");
+        }
+    }
+
+    /// An empty secret is the same as no secret
+    pub fn turtle_secret(&self) -> Option<&str> {
+        self.turtle_secret.as_deref().filter(|secret| !secret.is_empty())
+    }
+
+    pub fn accepts_turtle(&self, secret: &str) -> bool {
+        match self.turtle_secret() {
+            Some(expected) => secret_eq(expected, secret),
+            None => true,
+        }
+    }
+
+    /// Unlike [scope](AuthConfig::scope) there has to be a configured operator token, an open api does not count
+    pub fn is_operator_token(&self, token: Option<&str>) -> bool {
+        token.is_some_and(|token| self.operator_tokens.iter().any(|expected| secret_eq(expected, token)))
+    }
+
+    /// # Returns
+    /// None if the token is not valid
+    pub fn scope(&self, token: Option<&str>) -> Option<ApiScope> {
+        if self.read_tokens.is_empty() && self.operator_tokens.is_empty() {
+            return Some(ApiScope::Operator);
+        }
+
+        let token = token?;
+        if self.operator_tokens.iter().any(|expected| secret_eq(expected, token)) {
+            Some(ApiScope::Operator)
+        } else if self.read_tokens.iter().any(|expected| secret_eq(expected, token)) {
+            Some(ApiScope::Read)
+        } else {
+            None
+        }
+    }
+
+    /// Any origin is allowed when none were configured
+    pub fn cors_layer(&self) -> CorsLayer {
+        let origin = if self.cors_origins.is_empty() {
+            AllowOrigin::from(Any)
+        } else {
+            AllowOrigin::list(self.cors_origins.iter().filter_map(|origin| match origin.parse::<HeaderValue>() {
+                Ok(val) => Some(val),
+                Err(_) => {
+                    warn!("Invalid CORS origin {origin}");
+                    None
+                }
+            }))
+        };
+
+        CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods(Any)
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+    }
+}
+
+/// Bearer token from the Authorization header, browsers cannot set headers on websockets so `?token=` works too
+pub fn request_token<B>(request: &Request<B>) -> Option<Cow<'_, str>> {
+    let header_token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.strip_prefix("Bearer "));
+
+    match header_token {
+        Some(token) => Some(Cow::Borrowed(token)),
+        None => query_token(request.uri()),
+    }
+}
+
+/// Percent decoded `?token=` value
+fn query_token(uri: &Uri) -> Option<Cow<'_, str>> {
+    form_urlencoded::parse(uri.query()?.as_bytes())
+        .find_map(|(key, val)| (key == "token").then_some(val))
+}
+
+/// Uri with the `?token=` value replaced, see [request_token]
+fn redacted_uri(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+
+    let query = query
+        .split('&')
+        .map(|pair| if pair.starts_with("token=") { "token=<redacted>" } else { pair })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{query}", uri.path())
+}
+
+/// Same span as tower_http DefaultMakeSpan with headers, tokens in the query are not logged
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    tracing::debug_span!(
+        "request",
+        method = %request.method(),
+        uri = %redacted_uri(request.uri()),
+        version = ?request.version(),
+        headers = ?request.headers(),
+    )
+}
+
+async fn require_scope<B>(scope: ApiScope, request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    match config().auth.scope(request_token(&request).as_deref()) {
+        Some(token_scope) if token_scope >= scope => Ok(next.run(request).await),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+pub async fn require_read<B>(request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    require_scope(ApiScope::Read, request, next).await
+}
+
+pub async fn require_operator<B>(request: Request<B>, next: Next<B>) -> Result<Response, StatusCode> {
+    require_scope(ApiScope::Operator, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use super::{query_token, redacted_uri};
+
+    #[test]
+    fn test_token_is_redacted() {
+        let uri: Uri = "/events/?token=secret&world=overworld".parse().unwrap();
+        assert_eq!(redacted_uri(&uri), "/events/?token=<redacted>&world=overworld");
+
+        let uri: Uri = "/client.lua?x=1&token=secret".parse().unwrap();
+        assert_eq!(redacted_uri(&uri), "/client.lua?x=1&token=<redacted>");
+
+        let uri: Uri = "/turtle/".parse().unwrap();
+        assert_eq!(redacted_uri(&uri), "/turtle/");
+    }
+
+    #[test]
+    fn test_query_token_is_decoded() {
+        let uri: Uri = "/events/?world=overworld&token=a%2Bb%26c%3D".parse().unwrap();
+        assert_eq!(query_token(&uri).as_deref(), Some("a+b&c="));
+
+        let uri: Uri = "/events/?token=plain".parse().unwrap();
+        assert_eq!(query_token(&uri).as_deref(), Some("plain"));
+
+        let uri: Uri = "/events/?world=overworld".parse().unwrap();
+        assert_eq!(query_token(&uri), None);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{auth::AuthConfig, storage::StorageKind};
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+static DEFAULT_CONFIG_PATH: &str = "web_turtle.toml";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Cannot read config file {0} ({1})")]
+    IoError(PathBuf, std::io::Error),
+    #[error("Invalid config file {0} ({1})")]
+    TomlError(PathBuf, toml::de::Error),
+}
+
+/// Command line flags, they override the config file
+#[derive(Parser, Debug)]
+#[command(about = "Backend of web_turtle")]
+struct Cli {
+    /// TOML config file, web_turtle.toml is used if it exists
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Address the http server listens on
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+    /// Directory with the turtle and world files
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Where turtles and worlds are saved
+    #[arg(long, value_enum)]
+    storage: Option<StorageKind>,
+    /// Url turtles use to reach the backend, written into the served client
+    #[arg(long)]
+    public_url: Option<String>,
+    /// tracing filter, RUST_LOG overrides it
+    #[arg(long)]
+    log_filter: Option<String>,
+    /// Keep relative coordinates instead of locating turtles with gps
+    #[arg(long)]
+    no_gps: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub data_dir: PathBuf,
+    pub storage: StorageKind,
+    /// Url turtles use to reach the backend (like http://example.com:8000), the request host is used if it is not set
+    pub public_url: Option<String>,
+    pub log_filter: String,
+    /// Locate turtles with gps when they connect, locating the heading moves the turtle
+    pub gps: bool,
+    pub timeouts: TimeoutConfig,
+    pub auth: AuthConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            data_dir: PathBuf::from("turtle_database"),
+            storage: StorageKind::default(),
+            public_url: None,
+            log_filter: "backend=debug,tower_http=debug".to_string(),
+            gps: false,
+            timeouts: TimeoutConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+/// Every timeout is in seconds
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub command: u64,
+    /// Digging the sides turns the turtle twice
+    pub dig: u64,
+    /// How long an action waits to queue a command on a busy socket
+    pub queue: u64,
+    pub handshake: u64,
+    /// Idle time before the socket is checked
+    pub heartbeat_interval: u64,
+    pub heartbeat: u64,
+    /// How often changed chunks are saved, a crash loses at most this much of the world
+    pub world_flush: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            command: 5,
+            dig: 10,
+            queue: 10,
+            handshake: 5,
+            heartbeat_interval: 10,
+            heartbeat: 5,
+            world_flush: 5,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn command(&self) -> Duration {
+        Duration::from_secs(self.command)
+    }
+
+    pub fn dig(&self) -> Duration {
+        Duration::from_secs(self.dig)
+    }
+
+    pub fn queue(&self) -> Duration {
+        Duration::from_secs(self.queue)
+    }
+
+    pub fn handshake(&self) -> Duration {
+        Duration::from_secs(self.handshake)
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval)
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat)
+    }
+
+    pub fn world_flush(&self) -> Duration {
+        Duration::from_secs(self.world_flush)
+    }
+}
+
+/// Reads the config file and the command line, has to be called before [config]
+pub fn load() -> Result<&'static Config, ConfigError> {
+    let cli = Cli::parse();
+
+    //A missing default config file is fine, a missing file that was asked for is not
+    let path = cli.config.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+    let mut config = match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str::<Config>(&text).map_err(|err| ConfigError::TomlError(path, err))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && cli.config.is_none() => Config::default(),
+        Err(err) => return Err(ConfigError::IoError(path, err)),
+    };
+
+    if let Some(listen) = cli.listen {
+        config.listen = listen;
+    }
+    if let Some(data_dir) = cli.data_dir {
+        config.data_dir = data_dir;
+    }
+    if let Some(storage) = cli.storage {
+        config.storage = storage;
+    }
+    if let Some(public_url) = cli.public_url {
+        config.public_url = Some(public_url);
+    }
+    if let Some(log_filter) = cli.log_filter {
+        config.log_filter = log_filter;
+    }
+    if cli.no_gps {
+        config.gps = false;
+    }
+
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("Config is loaded at startup")
+}
@@ -1,24 +1,13 @@
-use std::{env, num::TryFromIntError, path::PathBuf, str::Utf8Error, io::SeekFrom};
+use std::{collections::HashMap, sync::Arc};
 
-use bytes::{Bytes, BytesMut};
-use once_cell::sync::Lazy;
-use shared::{JsonTurtle, world_structure::TurtleWorld};
-use tempfile::NamedTempFile;
+use bytes::Bytes;
+use shared::{JsonTurtle, world_structure::{ChunkLocation, TurtleWorld}, DEFAULT_WORLD_NAME};
 use thiserror::Error;
-use tokio::{fs::{File, OpenOptions}, io::{AsyncReadExt, AsyncWriteExt, AsyncSeekExt}};
+use tokio::sync::Mutex;
+use tracing::{error, info};
 use uuid::Uuid;
 
-//this is allowed to panic, if it ever fails all of our code is usless
-//this also can block but it is the best way to handle this
-static DATA_DIR: Lazy<PathBuf> = Lazy::new(|| {
-    let mut current_dir = env::current_dir().expect("Cannot get current_dir");
-    current_dir.push("turtle_database");
-    
-    if !current_dir.try_exists().expect("Checking if database dir exist failed") {
-        std::fs::create_dir_all(&current_dir).expect("Cannot create database dir");
-    };
-    current_dir
-});
+use crate::storage::{validate_world_name, TurtleStorage, WorldChanges};
 
 #[derive(Error, Debug)]
 pub enum DatabaseActionError {
@@ -26,120 +15,206 @@ pub enum DatabaseActionError {
     IoError(#[from] std::io::Error),
     #[error(transparent)]
     JsonError(#[from] serde_json::Error),
-    #[error("Cannot convert int types")]
-    IntError(#[from] TryFromIntError),
+    #[error(transparent)]
+    SledError(#[from] sled::Error),
     #[error(transparent)]
     DynamicError(#[from] Box<dyn std::error::Error + Send + Sync>),
-    #[error("Data in json file is not UTF-8")]
-    UtfError(#[from] Utf8Error)
+    #[error("Invalid world name ({0})")]
+    InvalidWorldName(String)
+}
+
+/// World shared by every turtle that is in the same minecraft dimension
+pub type SharedWorld = Arc<Mutex<WorldDatabase>>;
+/// Dirty chunks, dirty palette entries and their encoded data, see [WorldDatabase::take_changes]
+pub type TakenChanges = (Vec<ChunkLocation>, Vec<usize>, WorldChanges);
+
+#[derive(Debug)]
+pub struct WorldDatabase {
+    name: String,
+    /// Serialized world for the api, cleared when the world is borrowed mutably
+    raw_world_bytes: Option<Bytes>,
+    world: TurtleWorld,
+    storage: Arc<dyn TurtleStorage>,
+}
+
+/// Keeps every loaded world so turtles in the same world get the same [SharedWorld]
+#[derive(Debug)]
+pub struct WorldRegistry {
+    worlds: Mutex<HashMap<String, SharedWorld>>,
+    /// Two flushes of one world would write its changes out of order
+    flush_lock: Mutex<()>,
+    storage: Arc<dyn TurtleStorage>,
 }
 
 #[derive(Debug)]
 pub struct TurtleDatabase {
-    world_file: File,
-    json_file: File,
-    raw_world_bytes: Bytes,
     pub turtle_data: JsonTurtle,
-    pub world: TurtleWorld,
+    pub world: SharedWorld,
+    storage: Arc<dyn TurtleStorage>,
 }
 
-impl TurtleDatabase {
-    pub async fn create_from_id(id: Uuid) -> Result<Self, DatabaseActionError> {
-        let mut path = DATA_DIR.clone();
-        path.push(id.simple().to_string());
-
-        let json_file_path = path.with_extension("json");
-        let world_file_path = path.with_extension("world");
-     
-        let mut json_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(json_file_path)
-            .await?;
-       
-        let json_len = json_file.metadata().await?.len();
-
-        let json_turtle: JsonTurtle = if json_len == 0 {
-            println!("New turtle!");
-            JsonTurtle {
-                uuid: id.clone(),
-                x: 0,
-                y: 0,
-                z: 0,
-                rotation: shared::JsonTurtleDirection::Forward,
-            }
-        } else {
-            let mut bytes = Vec::new();
-            json_file.read_to_end(&mut bytes).await?;
-            println!("LL: {bytes:x?}");
-            println!("??: {}", std::str::from_utf8(&bytes).unwrap());
-            serde_json::from_slice(&bytes)?
-        };
+impl WorldDatabase {
+    pub async fn create_from_name(name: &str, storage: Arc<dyn TurtleStorage>) -> Result<Self, DatabaseActionError> {
+        validate_world_name(name)?;
 
-        let mut world_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(world_file_path)
-            .await?;
-
-        let world_len = world_file.metadata().await?.len();
-        let (turtle_world, turtle_bytes) = if world_len == 0 {
-            (TurtleWorld::new(), Bytes::new())
-        } else {
-            let mut bytes = Vec::with_capacity(world_len.try_into()?);
-            world_file.read_to_end(&mut bytes).await?;
-            let bytes: Bytes = bytes.into();
-            (TurtleWorld::from_bytes(bytes.clone())?, bytes)
+        let (world, is_new) = match storage.load_world(name).await? {
+            Some(world) => (world, false),
+            None => (TurtleWorld::new(), true),
         };
 
-        let mut database = Self {
-            world_file,
-            json_file,
-            raw_world_bytes: turtle_bytes,
-            turtle_data: json_turtle,
-            world: turtle_world
+        let database = Self {
+            name: name.to_string(),
+            raw_world_bytes: None,
+            world,
+            storage
         };
 
-        if json_len == 0 && world_len == 0 {
-            database.save().await?;
+        if is_new {
+            database.storage.save_world(&database.name, &database.world).await?;
         }
 
         Ok(database)
     }
 
-    pub async fn save(&mut self) -> Result<(), DatabaseActionError> {
-        let json_str = serde_json::to_vec(&self.turtle_data)?;
-        let mut world_bytes = self.world.to_bytes()?;
+    pub fn world(&self) -> &TurtleWorld {
+        &self.world
+    }
+
+    /// Changed chunks are saved by the next [flush](WorldRegistry::flush_all)
+    pub fn world_mut(&mut self) -> &mut TurtleWorld {
+        self.raw_world_bytes = None;
+        &mut self.world
+    }
+
+    /// Encodes the chunks and palette entries that changed since the last flush
+    /// # Returns
+    /// None if nothing changed
+    pub fn take_changes(&mut self) -> Result<Option<TakenChanges>, DatabaseActionError> {
+        let dirty = self.world.data.take_dirty();
+        let dirty_palette = self.world.pallete.take_dirty();
+        if dirty.is_empty() && dirty_palette.is_empty() {
+            return Ok(None);
+        }
+
+        match WorldChanges::encode(&self.world, &dirty, &dirty_palette) {
+            Ok(changes) => Ok(Some((dirty, dirty_palette, changes))),
+            Err(err) => {
+                self.mark_dirty(dirty, dirty_palette);
+                Err(err)
+            }
+        }
+    }
+
+    /// The chunks and palette entries are saved again by the next flush
+    pub fn mark_dirty(&mut self, chunks: Vec<ChunkLocation>, palette_ids: Vec<usize>) {
+        self.world.data.mark_dirty(chunks);
+        self.world.pallete.mark_dirty(palette_ids);
+    }
+
+    pub fn raw_world(&mut self) -> Result<Bytes, DatabaseActionError> {
+        match &self.raw_world_bytes {
+            Some(bytes) => Ok(bytes.clone()),
+            None => {
+                let bytes = self.world.to_bytes()?;
+                self.raw_world_bytes = Some(bytes.clone());
+                Ok(bytes)
+            }
+        }
+    }
 
-        self.raw_world_bytes = world_bytes.clone();
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
 
-        let named_tmp_world_file = NamedTempFile::new_in(DATA_DIR.clone())?;
-        let named_tmp_json_file = NamedTempFile::new_in(DATA_DIR.clone())?;
-        let (named_tmp_json_handle, named_tmp_json_path) = named_tmp_json_file.into_parts(); 
-        let (named_tmp_world_handle, named_tmp_world_path) = named_tmp_world_file.into_parts(); 
+impl WorldRegistry {
+    pub fn new(storage: Arc<dyn TurtleStorage>) -> Self {
+        Self {
+            worlds: Default::default(),
+            flush_lock: Default::default(),
+            storage
+        }
+    }
 
-        let mut tmp_world_file = File::from_std(named_tmp_world_handle);
-        let mut tmp_json_file = File::from_std(named_tmp_json_handle);
+    pub async fn get_or_load(&self, name: &str) -> Result<SharedWorld, DatabaseActionError> {
+        let mut guard = self.worlds.lock().await;
+        if let Some(world) = guard.get(name) {
+            return Ok(world.clone());
+        }
 
-        tmp_world_file.write_all(&mut world_bytes).await?;
-        tmp_json_file.write_all(&json_str).await?;
+        let world = Arc::new(Mutex::new(WorldDatabase::create_from_name(name, self.storage.clone()).await?));
+        guard.insert(name.to_string(), world.clone());
+        Ok(world)
+    }
 
-        tmp_world_file.flush().await?;
-        tmp_json_file.flush().await?;
+    /// Flushes every loaded world, errors are logged so one broken world does not stop the others
+    pub async fn flush_all(&self) {
+        let _flush_guard = self.flush_lock.lock().await;
+        let worlds: Vec<SharedWorld> = self.worlds.lock().await.values().cloned().collect();
+
+        for world in worlds {
+            //The world is only locked while the chunks are encoded, turtles do not wait for the disk
+            let (name, changes) = {
+                let mut world = world.lock().await;
+                (world.name().to_string(), world.take_changes())
+            };
+            let (dirty, dirty_palette, changes) = match changes {
+                Ok(Some(changes)) => changes,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Cannot encode world {name}: {err}");
+                    continue;
+                }
+            };
+
+            if let Err(err) = self.storage.save_changes(&name, &changes).await {
+                error!("Cannot save world {name}: {err}");
+                world.lock().await.mark_dirty(dirty, dirty_palette);
+            }
+        }
+    }
+}
 
-        let mut real_path = DATA_DIR.clone();
-        real_path.push(self.turtle_data.uuid.simple().to_string());
+impl TurtleDatabase {
+    pub async fn create_from_id(id: Uuid, worlds: &WorldRegistry) -> Result<Self, DatabaseActionError> {
+        let storage = worlds.storage.clone();
+        let stored_turtle = storage.load_turtle(&id).await?;
+        let is_new = stored_turtle.is_none();
 
-        tokio::fs::rename(named_tmp_json_path, real_path.with_extension("json")).await?;
-        tokio::fs::rename(named_tmp_world_path, real_path.with_extension("world")).await?;
+        let json_turtle = stored_turtle.unwrap_or_else(|| {
+            info!("New turtle {id}");
+            JsonTurtle {
+                uuid: id,
+                x: 0,
+                y: 0,
+                z: 0,
+                rotation: shared::JsonTurtleDirection::Forward,
+                fuel: shared::TurtleFuel::Unknown,
+                world: DEFAULT_WORLD_NAME.to_string(),
+                gps_located: false,
+                online: false,
+                last_seen: None,
+                excavation: None,
+            }
+        });
+
+        let world = worlds.get_or_load(&json_turtle.world).await?;
 
-        Ok(())
+        let database = Self {
+            turtle_data: json_turtle,
+            world,
+            storage
+        };
+
+        if is_new {
+            database.save().await?;
+        }
+
+        Ok(database)
     }
 
-    pub fn raw_world(&self) -> Bytes {
-        self.raw_world_bytes.clone()
+    /// Saves the turtle data, the world has to be saved separately
+    pub async fn save(&self) -> Result<(), DatabaseActionError> {
+        self.storage.save_turtle(&self.turtle_data).await
     }
 }
@@ -0,0 +1,54 @@
+use axum::{extract::{ws::{Message, WebSocket}, State, WebSocketUpgrade}, response::IntoResponse};
+use shared::TurtleEvent;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+use crate::TurtlesState;
+
+/// How many events can wait for a slow frontend before it starts missing them
+pub const EVENTS_CAPACITY: usize = 256;
+
+pub async fn events_handler(
+    ws: WebSocketUpgrade,
+    State(turtles): State<TurtlesState>
+) -> impl IntoResponse {
+    let events = turtles.events.subscribe();
+    ws.on_upgrade(move |socket| handle_events_socket(socket, events))
+}
+
+async fn handle_events_socket(mut socket: WebSocket, mut events: broadcast::Receiver<TurtleEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(val) => val,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Events socket is too slow, {skipped} events were skipped");
+                        TurtleEvent::Resync
+                    },
+                    Err(RecvError::Closed) => break,
+                };
+
+                let json = match serde_json::to_string(&event) {
+                    Ok(val) => val,
+                    Err(err) => {
+                        error!("Cannot serialize event {event:?}: {err}");
+                        continue;
+                    }
+                };
+
+                if socket.send(Message::Text(json)).await.is_err() {
+                    //Frontend is gone
+                    break;
+                }
+            },
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    //We do not expect anything from the frontend
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use shared::ExcavationState;
+use tracing::{debug, warn};
+
+use crate::turtle::{TurtleControl, TurtleHandle, TurtleRequestError};
+
+/// Lets the next job task start once this one is done
+struct JobGuard(Arc<TurtleControl>);
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.0.finish_job();
+    }
+}
+
+/// Digs the running excavation job of the turtle one stop at a time, so other requests can run in between.
+/// Nothing happens if the job is not running or a job task already drives the turtle
+pub fn spawn_excavation(handle: TurtleHandle) {
+    let is_running = handle.data.borrow().excavation.as_ref().is_some_and(|job| job.state == ExcavationState::Running);
+    if !is_running || !handle.control.start_job() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let _guard = JobGuard(handle.control.clone());
+        let uuid = handle.data.borrow().uuid;
+        debug!("Excavation job of turtle {uuid} started");
+
+        loop {
+            match handle.run(|mut turtle| async move { turtle.excavation_step().await }).await {
+                Ok(true) => continue,
+                Ok(false) => break,
+                //The step was cancelled before it started, the job would never continue on its own
+                Err(TurtleRequestError::Cancelled) => {
+                    let paused = handle.run(|mut turtle| async move {
+                        match &turtle.database.turtle_data.excavation {
+                            //Cancelled with the rest api
+                            Some(job) if job.state != ExcavationState::Running => Ok(()),
+                            _ => turtle.pause_excavation("Turtle was stopped").await.map(|_| ()),
+                        }
+                    }).await;
+
+                    match paused {
+                        Ok(Ok(())) => {},
+                        Ok(Err(err)) => warn!("Cannot pause excavation job of turtle {uuid}: {err}"),
+                        Err(err) => warn!("Cannot pause excavation job of turtle {uuid}: {err}"),
+                    }
+                    break;
+                },
+                Err(err) => {
+                    warn!("Excavation job of turtle {uuid} stopped: {err}");
+                    break;
+                }
+            }
+        }
+
+        debug!("Excavation job of turtle {uuid} stopped");
+    });
+}
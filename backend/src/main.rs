@@ -1,73 +1,156 @@
+mod auth;
+mod config;
 mod turtle;
 mod database;
-mod world;
-
-use std::{net::SocketAddr, sync::Arc, collections::HashMap, time::Duration, error::Error, str::FromStr};
-use axum::{Router, extract::{WebSocketUpgrade, ConnectInfo, ws::{WebSocket, Message}, State, Path, Query}, response::IntoResponse, routing::{get, put}, http::StatusCode, Json};
-use database::DatabaseActionError;
-use shared::{JsonTurtle, TurtleMoveResponse, JsonTurtleDirection};
-use tokio::{sync::{Mutex, mpsc}, time::timeout};
-use tower_http::{trace::{TraceLayer, DefaultMakeSpan}, cors::{CorsLayer, Any}};
-use tracing::{error, warn, debug};
+mod events;
+mod excavation;
+mod pathfinding;
+mod protocol;
+mod storage;
+
+use std::{net::SocketAddr, sync::Arc, collections::HashMap, error::Error, str::FromStr};
+use config::config;
+use axum::{Router, middleware, extract::{WebSocketUpgrade, ConnectInfo, ws::{WebSocket, Message}, State, Path, Query}, response::IntoResponse, routing::{get, post, put}, http::{header, HeaderMap, StatusCode}, Json};
+use shared::{world_structure::ChunkLocation, JsonTurtle, TurtleEvent, TurtleMoveResponse, JsonTurtleDirection, PlaceBlockRequest, TransferItemsRequest, ItemsSideRequest, RefuelRequest, TurtlePositionRequest, TurtleQueueResponse, ExcavationRequest, ExplorationRequest};
+use tokio::{sync::{RwLock, mpsc, broadcast}, time::{sleep, timeout}};
+use tower_http::{trace::TraceLayer, sensitive_headers::SetSensitiveRequestHeadersLayer};
+use tracing::{error, warn, debug, info};
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt};
-use turtle::{Turtle, TurtleRequestError, TurtleAsyncRequest};
+use protocol::{ClientHello, LuaValues, PROTOCOL_VERSION};
+use turtle::{Turtle, TurtleExcavationError, TurtleExploreError, TurtleWorldScanError, TurtleGotoError, TurtleMoveError, TurtleCommand, TurtleHandle, TurtleRequestError, TurtleAsyncRequest};
 use uuid::Uuid;
 
-use crate::database::TurtleDatabase;
+use crate::database::{TurtleDatabase, WorldRegistry};
 
-static GET_OS_LABEL_PAYLOAD: &str = "local ok, err = os.computerLabel() return ok";
+static GET_OS_LABEL_PAYLOAD: &str = "return os.getComputerLabel()";
+static HEARTBEAT_PAYLOAD: &str = "return true";
 
 #[derive(Clone)]
 struct TurtlesState {
-    turtles: Arc<Mutex<HashMap<Uuid, Turtle>>>,
+    /// Only held to look up a turtle, every turtle has its own lock
+    turtles: Arc<RwLock<HashMap<Uuid, TurtleHandle>>>,
+    worlds: Arc<WorldRegistry>,
+    events: broadcast::Sender<TurtleEvent>,
+}
+
+impl TurtlesState {
+    async fn get_turtle(&self, uuid: &Uuid) -> Result<TurtleHandle, (StatusCode, String)> {
+        let guard = self.turtles.read().await;
+
+        match guard.get(uuid) {
+            Some(handle) => Ok(handle.clone()),
+            None => Err((StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string()))
+        }
+    }
+}
+
+fn request_error(err: TurtleRequestError) -> (StatusCode, String) {
+    match err {
+        TurtleRequestError::Cancelled => (StatusCode::CONFLICT, err.to_string()),
+        TurtleRequestError::Offline => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+        err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let config = config::load()?;
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "backend=debug,tower_http=debug".into()),
+                .unwrap_or_else(|_| config.log_filter.clone().into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    let storage = storage::open_storage(config)?;
+    info!("Using {:?} storage in {}", config.storage, config.data_dir.display());
+
+    let worlds = Arc::new(WorldRegistry::new(storage));
     let state = TurtlesState {
         turtles: Default::default(),
+        worlds: worlds.clone(),
+        events: broadcast::channel(events::EVENTS_CAPACITY).0,
     };
 
-    // build our application with some routes
-    let app = Router::new()
-        .route("/turtle/", get(ws_handler))
-        .route("/turtle/:id/command/", put(command_turtle))
-        .route("/turtle/:id/move/", put(move_turtle))
+    config.auth.warn_if_open();
+
+    let read_routes = Router::new()
+        .route("/events/", get(events::events_handler))
         .route("/turtle/list/", get(list_turtles))
         .route("/turtle/:id/world/", get(get_world))
-        .route("/turtle/:id/destroy/", put(destroy_block))
         .route("/turtle/:id/inventory/", get(get_inventory))
+        .route("/turtle/:id/queue/", get(get_queue))
+        .route_layer(middleware::from_fn(auth::require_read));
+
+    let operator_routes = Router::new()
+        .route("/turtle/:id/command/", put(command_turtle))
+        .route("/turtle/:id/move/", put(move_turtle))
+        .route("/turtle/:id/position/", put(set_position))
+        .route("/turtle/:id/destroy/", put(destroy_block))
+        .route("/turtle/:id/place/", put(place_block))
+        .route("/turtle/:id/inventory/select/", put(select_slot))
+        .route("/turtle/:id/inventory/transfer/", put(transfer_items))
+        .route("/turtle/:id/inventory/drop/", put(drop_items))
+        .route("/turtle/:id/inventory/suck/", put(suck_items))
+        .route("/turtle/:id/inventory/equip/", put(equip))
+        .route("/turtle/:id/inventory/refuel/", put(refuel))
+        .route("/turtle/:id/stop/", put(stop_turtle))
+        .route("/turtle/:id/scan/", post(scan_turtle))
+        .route("/turtle/:id/explore/", post(explore_turtle))
+        .route("/turtle/:id/excavate/", post(start_excavation))
+        .route("/turtle/:id/excavate/pause/", put(pause_excavation))
+        .route("/turtle/:id/excavate/resume/", put(resume_excavation))
+        .route("/turtle/:id/excavate/cancel/", put(cancel_excavation))
+        .route_layer(middleware::from_fn(auth::require_operator));
+
+    // build our application with some routes
+    let app = Router::new()
+        //Turtles authenticate with the secret in the handshake
+        .route("/turtle/", get(ws_handler))
+        .route("/client.lua", get(get_client))
+        .merge(read_routes)
+        .merge(operator_routes)
         // logging so we can see whats going on
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::default().include_headers(true)),
-        )
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any),
+                .make_span_with(auth::request_span::<axum::body::Body>),
         )
+        //Tokens should never end up in the logs, the `?token=` query is redacted by auth::request_span
+        .layer(SetSensitiveRequestHeadersLayer::new([header::AUTHORIZATION]))
+        .layer(config.auth.cors_layer())
         .with_state(state);
 
     // run it with hyper
     //
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .await?;
+    tokio::spawn(flush_worlds(worlds.clone()));
+
+    tracing::debug!("listening on {}", config.listen);
+    let server = axum::Server::bind(&config.listen)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+
+    //Turtle sockets never close on their own, so the server is not shut down gracefully
+    tokio::select! {
+        result = server => result?,
+        _ = tokio::signal::ctrl_c() => info!("Shutting down"),
+    }
+    worlds.flush_all().await;
 
     return Ok(());
 }
 
+/// Saves changed chunks in the background, a world is only locked while its changes are encoded and not while they are written
+async fn flush_worlds(worlds: Arc<WorldRegistry>) {
+    let mut interval = tokio::time::interval(config().timeouts.world_flush());
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        worlds.flush_all().await;
+    }
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -78,20 +161,48 @@ async fn ws_handler(
     ws.on_upgrade(move |socket| handle_socket(socket, addr, turtles))
 }
 
+/// Without public_url the client connects back to the host it was downloaded from
+/// The turtle secret is only filled in for operators, `wget http://<backend>/client.lua?token=<token> startup.lua`
+async fn get_client(headers: HeaderMap, Query(query): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let config = config();
+
+    let url = match &config.public_url {
+        Some(public_url) => public_url.clone(),
+        None => {
+            let host = headers.get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("127.0.0.1:{}", config.listen.port()));
+            format!("http://{host}")
+        }
+    };
+    let url = match url.strip_prefix("https://") {
+        Some(rest) => format!("wss://{rest}"),
+        None => format!("ws://{}", url.strip_prefix("http://").unwrap_or(&url)),
+    };
+    let url = format!("{}/turtle/", url.trim_end_matches('/'));
+
+    //The endpoint is public, without operator tokens anyone could download the secret
+    let secret = if config.auth.is_operator_token(query.get("token").map(String::as_str)) {
+        config.auth.turtle_secret()
+    } else {
+        None
+    };
+
+    ([(header::CONTENT_TYPE, "text/x-lua")], protocol::render_client(&url, secret))
+}
+
 async fn command_turtle(
     State(turtles): State<TurtlesState>,
     Path(uuid): Path<String>,
     command: String 
 ) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
-    let mut guard = turtles.turtles.lock().await;
-
     let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
-    let turtle = match guard.get_mut(&uuid) {
-        Some(v) => v,
-        None => return Err((StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string())) 
-    };
+    let handle = turtles.get_turtle(&uuid).await?;
 
-    return turtle.command(&command).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()));
+    return handle.run(move |mut turtle| async move {
+        turtle.command(&command).await.map(|values| Json(values.0)).map_err(request_error)
+    }).await.map_err(request_error)?;
 }
 
 async fn move_turtle(
@@ -99,55 +210,137 @@ async fn move_turtle(
     Path(uuid): Path<String>,
     command: String 
 ) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
-    let mut guard = turtles.turtles.lock().await;
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    let direction = JsonTurtleDirection::from_str(&command).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+
+    handle.run(move |mut turtle| async move {
+        let changes = turtle.move_and_scan(direction).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        Ok(Json(TurtleMoveResponse {
+            x: turtle.database.turtle_data.x,
+            y: turtle.database.turtle_data.y,
+            z: turtle.database.turtle_data.z,
+            rotation: turtle.database.turtle_data.rotation.clone(),
+            fuel: turtle.database.turtle_data.fuel.clone(),
+            changes,
+        }))
+    }).await.map_err(request_error)?
+}
 
+/// Inspects every side of the turtle, see [Turtle::scan_full]
+async fn scan_turtle(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
     let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
-    let turtle = match guard.get_mut(&uuid) {
-        Some(v) => v,
-        None => return Err((StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string())) 
-    };
+    let handle = turtles.get_turtle(&uuid).await?;
 
-    let direction = match command.as_str() {
-        "forward" => JsonTurtleDirection::Forward,
-        "backward" => JsonTurtleDirection::Backward,
-        "left" => JsonTurtleDirection::Left,
-        "right" => JsonTurtleDirection::Right,
-        _ => return Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())),
-    };
+    handle.run(move |mut turtle| async move {
+        turtle.scan_full().await.map(Json).map_err(|err| match err {
+            TurtleWorldScanError::RequestError(err) => request_error(err),
+            err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })
+    }).await.map_err(request_error)?
+}
 
-    turtle.move_turtle(direction).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+/// Runs until the region is explored or the moves are used up, progress is sent on the events socket
+async fn explore_turtle(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<ExplorationRequest>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
 
-    //turtle.turtle_data.update(&mut conn).map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    handle.run(move |mut turtle| async move {
+        turtle.explore(request).await.map(Json).map_err(|err| match err {
+            TurtleExploreError::StartOutsideRegion => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+            TurtleExploreError::GotoError(TurtleGotoError::MoveError(TurtleMoveError::RequestError(err))) => request_error(err),
+            err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })
+    }).await.map_err(request_error)?
+}
 
-    let changes = turtle.scan_world_changes().await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+fn excavation_error(err: TurtleExcavationError) -> (StatusCode, String) {
+    match err {
+        TurtleExcavationError::NoJob => (StatusCode::NOT_FOUND, err.to_string()),
+        TurtleExcavationError::JobExists | TurtleExcavationError::InvalidState(_) => (StatusCode::CONFLICT, err.to_string()),
+        TurtleExcavationError::TooLarge => (StatusCode::BAD_REQUEST, err.to_string()),
+        err => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
 
-    Ok(Json(TurtleMoveResponse {
-        x: turtle.database.turtle_data.x,
-        y: turtle.database.turtle_data.y,
-        z: turtle.database.turtle_data.z,
-        rotation: turtle.database.turtle_data.rotation.clone(),
-        changes,
-    }))
+/// Starts the job in the background, progress is in the turtle data (see [JsonTurtle::excavation])
+async fn start_excavation(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<ExcavationRequest>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    let job = handle.run(move |mut turtle| async move {
+        turtle.start_excavation(request).await.map_err(excavation_error)
+    }).await.map_err(request_error)??;
+
+    excavation::spawn_excavation(handle);
+    Ok(Json(job))
+}
+
+/// The stop the turtle is digging is finished first
+async fn pause_excavation(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        turtle.pause_excavation("Paused by operator").await.map(Json).map_err(excavation_error)
+    }).await.map_err(request_error)?
+}
+
+async fn resume_excavation(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    let job = handle.run(move |mut turtle| async move {
+        turtle.resume_excavation().await.map_err(excavation_error)
+    }).await.map_err(request_error)??;
+
+    excavation::spawn_excavation(handle);
+    Ok(Json(job))
+}
+
+/// Stops the turtle right away, see [turtle::TurtleControl::stop]
+async fn cancel_excavation(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.control.stop();
+    handle.run(move |mut turtle| async move {
+        turtle.cancel_excavation().await.map(Json).map_err(excavation_error)
+    }).await.map_err(request_error)?
 }
 
 async fn list_turtles(
     State(turtles): State<TurtlesState>
 ) -> Json<Vec<JsonTurtle>>{
-    let turtles = turtles.turtles.lock().await;
-
-    return Json(
-        turtles.iter()
-        .enumerate()
-        .map(|(id, (uuid, turtle))| {
-            JsonTurtle {
-                uuid: *uuid,
-                x: turtle.database.turtle_data.x, 
-                y: turtle.database.turtle_data.y,
-                z: turtle.database.turtle_data.z,
-                rotation: turtle.database.turtle_data.rotation.clone(),
-            }
-        })
-        .collect());
+    let turtles = turtles.turtles.read().await;
+
+    //Snapshots are used so a busy turtle does not block the list
+    Json(
+        turtles.values()
+        .map(|handle| handle.json())
+        .collect())
 }
 
 async fn get_world(
@@ -155,15 +348,72 @@ async fn get_world(
     Path(uuid): Path<Uuid>,
     Query(params): Query<HashMap<String, i32>>
 ) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
-    let mut guard = turtles.turtles.lock().await;
+    let handle = turtles.get_turtle(&uuid).await?;
+    let region = world_region(&params).map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    //The world is read without waiting for the turtle, it could be busy for a long time
+    let world_name = handle.data.borrow().world.clone();
+    let world = turtles.worlds.get_or_load(&world_name).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let mut world = world.lock().await;
+
+    match region {
+        Some((min, max)) => world.world()
+            .region_to_bytes(&min, &max)
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+        None => world
+            .raw_world()
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
 
-    let turtle = match guard.get_mut(&uuid) {
-        Some(v) => v,
-        None => return Err((StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string())) 
-    };
+/// Chunk region requested with either `x, y, z, radius` (center chunk) or `min_x, min_y, min_z, max_x, max_y, max_z`
+/// # Returns
+/// Min and max chunk of the region, None when the whole world was requested
+fn world_region(params: &HashMap<String, i32>) -> Result<Option<(ChunkLocation, ChunkLocation)>, &'static str> {
+    //Chunk y is an i8, anything outside of it can not exist anyway
+    let chunk_y = |y: i32| y.clamp(i8::MIN.into(), i8::MAX.into()) as i8;
+
+    if let Some(radius) = params.get("radius") {
+        let (x, y, z) = match (params.get("x"), params.get("y"), params.get("z")) {
+            (Some(x), Some(y), Some(z)) => (*x, *y, *z),
+            _ => return Err("Radius requires x, y and z")
+        };
+        if *radius < 0 {
+            return Err("Radius cannot be negative");
+        }
+
+        return Ok(Some((
+            ChunkLocation::xyz(x.saturating_sub(*radius), chunk_y(y.saturating_sub(*radius)), z.saturating_sub(*radius)),
+            ChunkLocation::xyz(x.saturating_add(*radius), chunk_y(y.saturating_add(*radius)), z.saturating_add(*radius)),
+        )));
+    }
+
+    let keys = ["min_x", "min_y", "min_z", "max_x", "max_y", "max_z"];
+    let values: Vec<i32> = keys.iter().filter_map(|key| params.get(*key).copied()).collect();
+    match values[..] {
+        [] => Ok(None),
+        [min_x, min_y, min_z, max_x, max_y, max_z] => Ok(Some((
+            ChunkLocation::xyz(min_x, chunk_y(min_y), min_z),
+            ChunkLocation::xyz(max_x, chunk_y(max_y), max_z),
+        ))),
+        _ => Err("Region requires min_x, min_y, min_z, max_x, max_y and max_z")
+    }
+}
+
+async fn set_position(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<TurtlePositionRequest>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+    let worlds = turtles.worlds.clone();
+
+    handle.run(move |mut turtle| async move {
+        turtle.set_position(request, &worlds).await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
 
-    let world = turtle.database.raw_world();
-    Ok(world)
+        Ok(Json(turtle.database.turtle_data.clone()))
+    }).await.map_err(request_error)?
 }
 
 async fn destroy_block(
@@ -171,40 +421,168 @@ async fn destroy_block(
     Path(uuid): Path<String>,
     command: String 
 ) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
-    let mut guard = turtles.turtles.lock().await;
-
     let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
-    let turtle = match guard.get_mut(&uuid) {
-        Some(v) => v,
-        None => return Err((StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string())) 
-    };
+    let handle = turtles.get_turtle(&uuid).await?;
 
     let side = JsonTurtleDirection::from_str(&command).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
 
-    match turtle.destroy_block(side).await {
-        Ok(val) => return Ok(Json(val)),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
-    }
+    handle.run(move |mut turtle| async move {
+        match turtle.destroy_block(side).await {
+            Ok(val) => Ok(Json(val)),
+            Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+        }
+    }).await.map_err(request_error)?
+}
+
+async fn place_block(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<PlaceBlockRequest>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        match turtle.place_block(request.side, request.slot).await {
+            Ok(val) => Ok(Json(val)),
+            Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+        }
+    }).await.map_err(request_error)?
 }
 
 async fn get_inventory(
     State(turtles): State<TurtlesState>,
     Path(uuid): Path<String>
 ) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
-    let mut guard = turtles.turtles.lock().await;
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.get_inventory().await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
 
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
+}
+
+async fn select_slot(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    command: String
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
     let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
-    let turtle = match guard.get_mut(&uuid) {
-        Some(v) => v,
-        None => return Err((StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string())) 
-    };
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    let slot = command.parse::<u8>().or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
 
-    let inventory = turtle.get_inventory().await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.select_slot(slot).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
 
-    Ok(Json(inventory))
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
 }
 
-async fn handle_socket(mut socket: WebSocket, _addr: SocketAddr, turtles: TurtlesState)  {
+async fn transfer_items(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<TransferItemsRequest>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.transfer_items(request).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
+}
+
+async fn drop_items(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<ItemsSideRequest>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.drop_items(request).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
+}
+
+async fn suck_items(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<ItemsSideRequest>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.suck_items(request).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
+}
+
+async fn equip(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    command: String
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    let side = JsonTurtleDirection::from_str(&command).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.equip(side).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
+}
+
+async fn refuel(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>,
+    Json(request): Json<RefuelRequest>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.run(move |mut turtle| async move {
+        let inventory = turtle.refuel(request).await.map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+        Ok(Json(inventory))
+    }).await.map_err(request_error)?
+}
+
+async fn get_queue(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    Ok(Json(TurtleQueueResponse {
+        depth: handle.control.queue_depth()
+    }))
+}
+
+async fn stop_turtle(
+    State(turtles): State<TurtlesState>,
+    Path(uuid): Path<String>
+) -> Result<impl IntoResponse, (StatusCode, impl IntoResponse)> {
+    let uuid = Uuid::parse_str(&uuid).or(Err((StatusCode::BAD_REQUEST, StatusCode::BAD_REQUEST.to_string())))?;
+    let handle = turtles.get_turtle(&uuid).await?;
+
+    handle.control.stop();
+
+    Ok(StatusCode::OK)
+}
+
+async fn handle_socket(mut socket: WebSocket, addr: SocketAddr, turtles: TurtlesState)  {
     macro_rules! close_socket {
         () => {
              if let Err(close_err) = socket.close().await {
@@ -215,13 +593,14 @@ async fn handle_socket(mut socket: WebSocket, _addr: SocketAddr, turtles: Turtle
     macro_rules! send_payload {
         ($payload:expr) => {
             'main: {
+                let (request_id, request) = protocol::encode_request(&$payload);
                 //For some reason the user disconected
-                if let Err(_) = socket.send(Message::Text($payload.to_string())).await {
+                if socket.send(Message::Text(request)).await.is_err() {
                     close_socket!();
                     return; 
                 }
 
-                let socket_msg = match timeout(Duration::from_secs(5), socket.recv()).await {
+                let socket_msg = match timeout(config().timeouts.command(), socket.recv()).await {
                     Ok(val) => {
                         match val {
                             Some(val) => {
@@ -254,116 +633,238 @@ async fn handle_socket(mut socket: WebSocket, _addr: SocketAddr, turtles: Turtle
                         return;           
                     }
                 };
-                break 'main socket_msg
+                break 'main protocol::decode_response(&socket_msg, request_id)
             }
         };
     }
 
     let (tx, mut rx) = mpsc::channel::<TurtleAsyncRequest>(64);
 
-    //TODO: Attempt to get turtle by uuid
+    //The client says hello before it runs anything
+    let hello = match timeout(config().timeouts.handshake(), socket.recv()).await {
+        Ok(Some(Ok(Message::Text(val)))) => serde_json::from_str::<ClientHello>(&val),
+        _ => {
+            warn!("Turtle connecting from {addr} did not send the handshake");
+            close_socket!();
+            return;
+        }
+    };
+    let hello = match hello {
+        Ok(val) if val.version == PROTOCOL_VERSION => val,
+        Ok(val) => {
+            warn!("Turtle connecting from {addr} uses protocol version {} (expected {PROTOCOL_VERSION}), update its client from /client.lua", val.version);
+            close_socket!();
+            return;
+        },
+        Err(_) => {
+            warn!("Turtle connecting from {addr} uses an old client, update it from /client.lua");
+            close_socket!();
+            return;
+        }
+    };
+    if !config().auth.accepts_turtle(&hello.secret) {
+        warn!("Turtle connecting from {addr} sent an invalid secret");
+        close_socket!();
+        return;
+    }
 
     let uuid = {
-        let socket_msg = send_payload!(GET_OS_LABEL_PAYLOAD);
-        let parsed_uuid = Uuid::try_parse(&socket_msg);
-
-        if socket_msg == "nil" || parsed_uuid.is_err() {
-            //We have a unknown turtle
-            let new_uuid = Uuid::new_v4();
-            let set_payload = format!("return os.setComputerLabel(\"{}\")", new_uuid.simple().to_string());
-            let _ = send_payload!(set_payload);
-
-            new_uuid
-        } else {
-            parsed_uuid.unwrap()
+        let label = send_payload!(GET_OS_LABEL_PAYLOAD);
+        let parsed_uuid = label.ok().and_then(|label| {
+            label.get(0).as_str().and_then(|label| Uuid::try_parse(label).ok())
+        });
+
+        match parsed_uuid {
+            Some(uuid) => uuid,
+            None => {
+                //We have a unknown turtle
+                let new_uuid = Uuid::new_v4();
+                let set_payload = format!("os.setComputerLabel(\"{}\")", new_uuid.simple());
+                if let Err(err) = send_payload!(set_payload) {
+                    warn!("Cannot label turtle {new_uuid}: {err}");
+                }
+
+                new_uuid
+            }
         }
     };
 
-    let database = match TurtleDatabase::create_from_id(uuid).await {
-        Ok(val) => val,
-        Err(err) => {
-            error!("Database error for turtle {uuid:?} Err: {err}");
-            Err::<(), DatabaseActionError>(err).unwrap(); 
-            close_socket!();
-            return;
+    //A turtle that reconnects (or comes back online) takes over its existing entry
+    let existing = turtles.turtles.read().await.get(&uuid).cloned();
+    let resumed = existing.is_some();
+    let turtle = match existing {
+        Some(handle) => handle,
+        None => {
+            let database = match TurtleDatabase::create_from_id(uuid, &turtles.worlds).await {
+                Ok(val) => val,
+                Err(err) => {
+                    error!("Database error for turtle {uuid:?} Err: {err}");
+                    close_socket!();
+                    return;
+                }
+            };
+
+            let turtle = TurtleHandle::new(Turtle::new(database, turtles.events.clone()));
+            //Another socket of the same turtle could have been faster
+            turtles.turtles.write().await.entry(uuid).or_insert(turtle).clone()
         }
     };
 
-    let turtle = Turtle::new(uuid, database, tx);
+    let control = turtle.control.clone();
+    let (connection_id, mut replaced) = control.attach(tx);
 
-    //Add new turtle
-    let mut guard = turtles.turtles.lock().await;
-    if let Some(_) = guard.get(&uuid) {
-        warn!("Turtle overwrite attempt");
-        close_socket!();
-        return;
+    if resumed {
+        info!("Turtle {uuid} reconnected");
     }
+    let _ = turtles.events.send(TurtleEvent::TurtleConnected(turtle.json()));
+
+    //Commands are only answered once the main loop runs
+    let resumed_turtle = turtle.clone();
+    tokio::spawn(async move {
+        match resumed_turtle.run(|mut turtle| async move { turtle.resume().await }).await {
+            Ok(Ok(())) => {},
+            Ok(Err(err)) => warn!("Cannot resume turtle {uuid}: {err}"),
+            Err(err) => warn!("Cannot resume turtle {uuid}: {err}"),
+        }
 
-    guard.insert(uuid.clone(), turtle);
-    drop(guard);
+        //A job that was running when the turtle went offline (or the backend stopped) continues where the turtle really is
+        excavation::spawn_excavation(resumed_turtle);
+    });
 
     'main_loop: loop {
-        if let Some(request) = rx.recv().await {
-            let response: Result<String, TurtleRequestError> = 'response: {
+        let request = tokio::select! {
+            request = rx.recv() => match request {
+                Some(val) => val,
+                None => break 'main_loop
+            },
+            _ = &mut replaced => {
+                info!("Turtle {uuid} connected again, closing the old socket");
+                let _ = socket.close().await;
+                break 'main_loop;
+            },
+            _ = sleep(config().timeouts.heartbeat_interval()) => {
+                //Dead sockets are noticed even if nobody uses the turtle
+                let (request_id, heartbeat) = protocol::encode_request(HEARTBEAT_PAYLOAD);
+                if socket.send(Message::Text(heartbeat)).await.is_err() {
+                    break 'main_loop;
+                }
+
+                let reply = loop {
+                    match timeout(config().timeouts.heartbeat(), socket.recv()).await {
+                        Ok(Some(Ok(Message::Ping(_) | Message::Pong(_)))) => continue,
+                        reply => break reply,
+                    }
+                };
+                match reply {
+                    Ok(Some(Ok(Message::Text(msg)))) if protocol::decode_response(&msg, request_id).is_ok() => {
+                        control.touch();
+                        continue 'main_loop;
+                    },
+                    _ => {
+                        warn!("Turtle {uuid} missed a heartbeat");
+                        let _ = socket.close().await;
+                        break 'main_loop;
+                    }
+                }
+            }
+        };
+
+        //Nobody waits for the answer anymore
+        if request.response.is_closed() {
+            continue;
+        }
 
-                if let Err(err) = socket.send(Message::Text(request.request)).await {
+        if request.generation != control.generation() {
+            let _ = request.response.send(Err(TurtleRequestError::Cancelled));
+            continue;
+        }
+
+        let response: Result<Vec<LuaValues>, TurtleRequestError> = 'response: {
+            //Every command is sent right away, the turtle answers them in order
+            let mut pending: HashMap<u64, usize> = HashMap::with_capacity(request.commands.len());
+            for (i, command) in request.commands.iter().enumerate() {
+                let (request_id, message) = protocol::encode_request(&command.payload());
+                if let Err(err) = socket.send(Message::Text(message)).await {
                     break 'response Err(TurtleRequestError::DataSendError(err))
                 };
+                pending.insert(request_id, i);
+            }
 
+            //The turtle runs the commands one after another, so every reply gets the longest timeout
+            let reply_timeout = request.commands.iter()
+                .map(TurtleCommand::timeout)
+                .max()
+                .unwrap_or_default();
+            let mut replies: Vec<Option<Result<LuaValues, TurtleRequestError>>> = request.commands.iter().map(|_| None).collect();
+
+            while !pending.is_empty() {
+                let socket_msg = tokio::select! {
+                    socket_msg = timeout(reply_timeout, socket.recv()) => socket_msg,
+                    _ = &mut replaced => {
+                        info!("Turtle {uuid} connected again, closing the old socket");
+                        let _ = request.response.send(Err(TurtleRequestError::WsClosed));
+                        let _ = socket.close().await;
+                        break 'main_loop;
+                    }
+                };
 
-                let socket_msg = match timeout(Duration::from_secs(5), socket.recv()).await {
+                let socket_msg = match socket_msg {
                     Ok(val) => val,
                     Err(_) => {
-                        if let Err(_) = request.response.send(Err(TurtleRequestError::TimeOut)) {
+                        if request.response.send(Err(TurtleRequestError::TimeOut)).is_err() {
                             error!("Cannot send turtle request response!");
                         };
-                        
+
                         //We do not care if the socket close goes well!
                         let _ = socket.close().await;
                         break 'main_loop;
                     }
                 };
 
-                match socket_msg {
-                    Some(msg) => {
-                        match msg {
-                            Ok(msg) => {
-                                match msg {
-                                    Message::Text(msg) => break 'response Ok(msg),
-                                    Message::Close(_) => {
-                                         if let Err(_) = request.response.send(Err(TurtleRequestError::WsClosed)) {
-                                            error!("Cannot send turtle request response!");
-                                        };
-                                        break 'main_loop
-                                    },
-                                    _ => break 'response Err(TurtleRequestError::InvalidResponse)
-                                }
-                            },
-                            Err(_) => {
-                                if let Err(_) = request.response.send(Err(TurtleRequestError::WsClosed)) {
-                                    error!("Cannot send turtle request response!");
-                                };
-                                break 'main_loop
-                            },
-                        }
-                    },
-                    None => {
-                        if let Err(_) = request.response.send(Err(TurtleRequestError::WsClosed)) {
+                let msg = match socket_msg {
+                    Some(Ok(Message::Text(msg))) => msg,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        if request.response.send(Err(TurtleRequestError::WsClosed)).is_err() {
                             error!("Cannot send turtle request response!");
                         };
-                        break 'main_loop; 
+                        break 'main_loop
+                    },
+                    //Websocket keepalive, not an answer
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(_)) => break 'response Err(TurtleRequestError::InvalidResponse)
+                };
+                control.touch();
+
+                match protocol::decode_reply(&msg) {
+                    Ok((Some(reply_id), result)) => match pending.remove(&reply_id) {
+                        Some(i) => replies[i] = Some(result),
+                        //Reply to a request that already failed (for example a timed out heartbeat)
+                        None => debug!("Turtle {uuid} answered an unknown request {reply_id}"),
                     },
+                    Ok((None, _)) | Err(_) => break 'response Err(TurtleRequestError::InvalidResponse)
                 }
-            };
+            }
 
-            if let Err(_) = request.response.send(response) {
-                error!("Cannot send turtle request response!");
-                break 'main_loop;
-            };
-        }
+            replies.into_iter()
+                .map(|reply| reply.unwrap_or(Err(TurtleRequestError::InvalidResponse)))
+                .collect()
+        };
+
+        //The action is always awaited on its own task, a dropped receiver is not an error
+        if request.response.send(response).is_err() {
+            debug!("Turtle request response was not received");
+        };
     }
 
-    let mut guard = turtles.turtles.lock().await;
-    guard.remove(&uuid);
-    drop(guard);
+    //Requests that were queued for this socket will never be answered
+    rx.close();
+    while let Ok(request) = rx.try_recv() {
+        let _ = request.response.send(Err(TurtleRequestError::WsClosed));
+    }
+
+    //The turtle stays listed while it is offline
+    if control.detach(connection_id) {
+        info!("Turtle {uuid} is offline");
+        let _ = turtles.events.send(TurtleEvent::TurtleDisconnected { uuid });
+    }
 }
@@ -0,0 +1,278 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, HashSet, VecDeque}};
+
+use shared::world_structure::TurtleWorld;
+
+pub type BlockPosition = (i32, i32, i32);
+
+static AIR_COST: u32 = 1;
+//Unknown blocks are often air but the turtle could have to go around them
+static UNKNOWN_COST: u32 = 3;
+//Digging is slow and fills the inventory
+static DIG_COST: u32 = 6;
+//Keeps a target that cannot be reached from searching the whole world
+static MAX_VISITED_BLOCKS: usize = 200_000;
+
+static NEIGHBOURS: [BlockPosition; 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+
+/// # Returns
+/// Cost of moving into the block, None if the turtle cannot go there
+fn block_cost(world: &TurtleWorld, (x, y, z): BlockPosition, dig_region: Option<&Region>) -> Option<u32> {
+    //Chunk y is an i8, anything outside of it is not a real block
+    TurtleWorld::get_chunk_loc_from_global_xyz(x, y, z).ok()?;
+
+    match world.get_voxel_by_global_xyz(x, y, z) {
+        Some(voxel) if voxel.is_air() => Some(AIR_COST),
+        Some(voxel) if voxel.is_solid() && dig_region.is_some_and(|region| region.contains((x, y, z))) => Some(DIG_COST),
+        Some(voxel) if voxel.is_solid() => None,
+        _ => Some(UNKNOWN_COST),
+    }
+}
+
+pub fn distance(from: BlockPosition, to: BlockPosition) -> u32 {
+    from.0.abs_diff(to.0) + from.1.abs_diff(to.1) + from.2.abs_diff(to.2)
+}
+
+/// A* through the known world, blocked positions are never entered and known blocks are only dug inside the dig region
+/// # Returns
+/// Every position after start up to and including the target, None if there is no path
+pub fn find_path(world: &TurtleWorld, start: BlockPosition, target: BlockPosition, dig_region: Option<&Region>, blocked: &HashSet<BlockPosition>) -> Option<Vec<BlockPosition>> {
+    if start == target {
+        return Some(vec![]);
+    }
+    //Without this the search would only stop after visiting every reachable block
+    if blocked.contains(&target) || block_cost(world, target, dig_region).is_none() {
+        return None;
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut costs: HashMap<BlockPosition, u32> = HashMap::new();
+    let mut came_from: HashMap<BlockPosition, BlockPosition> = HashMap::new();
+
+    costs.insert(start, 0);
+    open.push(Reverse((distance(start, target) * AIR_COST, start)));
+
+    while let Some(Reverse((_, position))) = open.pop() {
+        if position == target {
+            let mut path = vec![position];
+            let mut current = position;
+            while let Some(previous) = came_from.get(&current) {
+                if *previous == start {
+                    break;
+                }
+                path.push(*previous);
+                current = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        if costs.len() > MAX_VISITED_BLOCKS {
+            return None;
+        }
+
+        let cost = costs[&position];
+        for (x_diff, y_diff, z_diff) in NEIGHBOURS {
+            let next = (position.0 + x_diff, position.1 + y_diff, position.2 + z_diff);
+            if blocked.contains(&next) {
+                continue;
+            }
+            let Some(step_cost) = block_cost(world, next, dig_region) else {
+                continue;
+            };
+
+            let next_cost = cost + step_cost;
+            if costs.get(&next).is_none_or(|known| next_cost < *known) {
+                costs.insert(next, next_cost);
+                came_from.insert(next, position);
+                open.push(Reverse((next_cost + distance(next, target) * AIR_COST, next)));
+            }
+        }
+    }
+
+    None
+}
+
+/// Cuboid between two corners (inclusive)
+#[derive(Debug, Clone)]
+pub struct Region {
+    min: BlockPosition,
+    max: BlockPosition,
+}
+
+impl Region {
+    pub fn new(from: BlockPosition, to: BlockPosition) -> Self {
+        Self {
+            min: (from.0.min(to.0), from.1.min(to.1), from.2.min(to.2)),
+            max: (from.0.max(to.0), from.1.max(to.1), from.2.max(to.2)),
+        }
+    }
+
+    pub fn contains(&self, (x, y, z): BlockPosition) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y) && (self.min.2..=self.max.2).contains(&z)
+    }
+}
+
+/// Frontier of the exploration, an unknown block of the region that is next to a known air block.
+/// Searched breadth first through the air of the region around the turtle
+/// # Returns
+/// The frontier block closest to the turtle, None if the region is explored
+pub fn find_frontier(world: &TurtleWorld, region: &Region, from: BlockPosition, skip: &HashSet<BlockPosition>) -> Option<BlockPosition> {
+    let mut visited = HashSet::from([from]);
+    let mut open = VecDeque::from([from]);
+
+    while let Some(position) = open.pop_front() {
+        if visited.len() > MAX_VISITED_BLOCKS {
+            return None;
+        }
+
+        for (x_diff, y_diff, z_diff) in NEIGHBOURS {
+            let next = (position.0 + x_diff, position.1 + y_diff, position.2 + z_diff);
+            if !region.contains(next) || TurtleWorld::get_chunk_loc_from_global_xyz(next.0, next.1, next.2).is_err() || !visited.insert(next) {
+                continue;
+            }
+
+            match world.get_voxel_by_global_xyz(next.0, next.1, next.2) {
+                Some(voxel) if voxel.is_air() => open.push_back(next),
+                Some(voxel) if voxel.is_solid() => {},
+                _ if skip.contains(&next) => {},
+                _ => return Some(next),
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use shared::world_structure::{TurtleVoxel, TurtleWorld};
+
+    use super::{distance, find_frontier, find_path, BlockPosition, Region};
+
+    fn set_voxel(world: &mut TurtleWorld, (x, y, z): BlockPosition, voxel: TurtleVoxel) {
+        let (loc, ..) = TurtleWorld::get_chunk_loc_from_global_xyz(x, y, z).expect("Block is outside of the world");
+        world.data.force_get_mut_chunk_by_loc(&loc).update_voxel_by_global_xyz(x, y, z, |old| {
+            *old = voxel;
+            Ok(())
+        }).expect("Cannot set block");
+    }
+
+    fn stone(world: &mut TurtleWorld) -> TurtleVoxel {
+        let (palette, _) = world.get_fields_mut();
+        let (id, _) = palette.get_pallete_index("minecraft:stone");
+        TurtleVoxel::id(id as u16)
+    }
+
+    fn fill(world: &mut TurtleWorld, from: BlockPosition, to: BlockPosition, voxel: TurtleVoxel) {
+        for x in from.0..=to.0 {
+            for y in from.1..=to.1 {
+                for z in from.2..=to.2 {
+                    set_voxel(world, (x, y, z), voxel);
+                }
+            }
+        }
+    }
+
+    fn assert_walkable(world: &TurtleWorld, start: BlockPosition, path: &[BlockPosition]) {
+        let mut previous = start;
+        for position in path {
+            assert_eq!(distance(previous, *position), 1, "{previous:?} -> {position:?} is not a single move");
+            let voxel = world.get_voxel_by_global_xyz(position.0, position.1, position.2);
+            assert!(!voxel.is_some_and(|voxel| voxel.is_solid()), "Path goes through the solid block {position:?}");
+            previous = *position;
+        }
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let mut world = TurtleWorld::new();
+        fill(&mut world, (0, 0, 0), (5, 0, 0), TurtleVoxel::air());
+
+        let path = find_path(&world, (0, 0, 0), (5, 0, 0), None, &HashSet::new()).expect("No path found");
+        assert_eq!(path, vec![(1, 0, 0), (2, 0, 0), (3, 0, 0), (4, 0, 0), (5, 0, 0)]);
+        assert_eq!(find_path(&world, (0, 0, 0), (0, 0, 0), None, &HashSet::new()), Some(vec![]));
+    }
+
+    #[test]
+    fn test_detour_around_obstacle() {
+        let mut world = TurtleWorld::new();
+        let stone = stone(&mut world);
+        fill(&mut world, (-5, -5, -5), (5, 5, 5), TurtleVoxel::air());
+        fill(&mut world, (0, -2, -2), (0, 2, 2), stone);
+
+        let path = find_path(&world, (-2, 0, 0), (2, 0, 0), None, &HashSet::new()).expect("No path found");
+        assert_walkable(&world, (-2, 0, 0), &path);
+        assert_eq!(path.last(), Some(&(2, 0, 0)));
+        assert!(path.len() > 4);
+
+        //Digging through the wall is cheaper than walking around it
+        let dig_region = Region::new((-5, -5, -5), (5, 5, 5));
+        let path = find_path(&world, (-2, 0, 0), (2, 0, 0), Some(&dig_region), &HashSet::new()).expect("No path found");
+        assert_eq!(path, vec![(-1, 0, 0), (0, 0, 0), (1, 0, 0), (2, 0, 0)]);
+
+        //The wall is outside of the region the turtle may dig in
+        let path = find_path(&world, (-2, 0, 0), (2, 0, 0), Some(&Region::new((1, -5, -5), (5, 5, 5))), &HashSet::new()).expect("No path found");
+        assert_walkable(&world, (-2, 0, 0), &path);
+
+        //Positions the turtle was blocked at are never entered
+        let blocked = HashSet::from([(-1, 0, 0)]);
+        let path = find_path(&world, (-2, 0, 0), (2, 0, 0), Some(&dig_region), &blocked).expect("No path found");
+        assert!(!path.contains(&(-1, 0, 0)));
+    }
+
+    #[test]
+    fn test_no_path() {
+        let mut world = TurtleWorld::new();
+        let stone = stone(&mut world);
+        fill(&mut world, (-1, -1, -1), (1, 1, 1), stone);
+        set_voxel(&mut world, (0, 0, 0), TurtleVoxel::air());
+
+        assert_eq!(find_path(&world, (0, 0, 0), (5, 0, 0), None, &HashSet::new()), None);
+        let dig_region = Region::new((-1, -1, -1), (1, 1, 1));
+        assert!(find_path(&world, (0, 0, 0), (5, 0, 0), Some(&dig_region), &HashSet::new()).is_some());
+        assert_eq!(find_path(&world, (0, 0, 0), (5, 0, 0), Some(&Region::new((2, 0, 0), (5, 0, 0))), &HashSet::new()), None);
+
+        //Solid target without digging
+        assert_eq!(find_path(&world, (5, 0, 0), (1, 0, 0), None, &HashSet::new()), None);
+    }
+
+    #[test]
+    fn test_visited_blocks_cap() {
+        //Unknown blocks cost more than the heuristic expects so a far target visits a lot of blocks
+        let world = TurtleWorld::new();
+        assert!(find_path(&world, (0, 0, 0), (10, 0, 10), None, &HashSet::new()).is_some());
+        assert_eq!(find_path(&world, (0, 0, 0), (1000, 0, 1000), None, &HashSet::new()), None);
+    }
+
+    #[test]
+    fn test_frontier_is_unknown_next_to_air() {
+        let mut world = TurtleWorld::new();
+        let stone = stone(&mut world);
+        let region = Region::new((5, 0, 0), (-5, 0, 0));
+        fill(&mut world, (-3, 0, 0), (3, 0, 0), TurtleVoxel::air());
+
+        //Blocks outside of the region are unknown too but they are not explored
+        assert_eq!(find_frontier(&world, &region, (2, 0, 0), &HashSet::new()), Some((4, 0, 0)));
+        assert_eq!(find_frontier(&world, &region, (-2, 0, 0), &HashSet::new()), Some((-4, 0, 0)));
+        assert_eq!(find_frontier(&world, &region, (2, 0, 0), &HashSet::from([(4, 0, 0)])), Some((-4, 0, 0)));
+
+        //Unknown blocks behind solid blocks cannot be reached
+        set_voxel(&mut world, (4, 0, 0), stone);
+        set_voxel(&mut world, (-4, 0, 0), stone);
+        assert_eq!(find_frontier(&world, &region, (2, 0, 0), &HashSet::new()), None);
+    }
+
+    #[test]
+    fn test_explored_region() {
+        let mut world = TurtleWorld::new();
+        let region = Region::new((-2, -2, -2), (2, 2, 2));
+        fill(&mut world, (-2, -2, -2), (2, 2, 2), TurtleVoxel::air());
+        assert!(region.contains((2, -2, 0)) && !region.contains((3, 0, 0)));
+        assert_eq!(find_frontier(&world, &region, (0, 0, 0), &HashSet::new()), None);
+
+        set_voxel(&mut world, (2, 2, 2), TurtleVoxel::unknown());
+        assert_eq!(find_frontier(&world, &region, (0, 0, 0), &HashSet::new()), Some((2, 2, 2)));
+    }
+}
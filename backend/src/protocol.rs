@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::turtle::TurtleRequestError;
+
+/// Bumped every time the messages below change, turtles with another version are rejected
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The turtle client, served on /client.lua so turtles can download it with wget
+pub static CLIENT_LUA: &str = include_str!("../../startup.lua");
+
+/// Client with the backend url and secret filled in
+pub fn render_client(url: &str, secret: Option<&str>) -> String {
+    CLIENT_LUA
+        .lines()
+        .map(|line| {
+            if line.starts_with("local URL = ") {
+                format!("local URL = {}", lua_string(url))
+            } else if line.starts_with("local SECRET = ") {
+                format!("local SECRET = {}", lua_string(secret.unwrap_or_default()))
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n") + "\n"
+}
+
+fn lua_string(val: &str) -> String {
+    let mut quoted = String::with_capacity(val.len() + 2);
+    quoted.push('"');
+    for char in val.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            char if char.is_ascii_control() => quoted.push_str(&format!("\\{:03}", char as u8)),
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// First message sent by the client
+#[derive(Deserialize, Debug)]
+pub struct ClientHello {
+    pub version: u32,
+    #[serde(default)]
+    pub secret: String,
+}
+
+#[derive(Serialize, Debug)]
+struct LuaRequest<'a> {
+    id: u64,
+    lua: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct LuaResponse {
+    /// Null if the client could not read the request
+    id: Option<u64>,
+    ok: bool,
+    #[serde(default)]
+    values: Vec<Value>,
+    error: Option<String>,
+}
+
+/// Everything the lua chunk returned, nil is Null
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LuaValues(pub Vec<Value>);
+
+impl LuaValues {
+    /// Missing values are nil, the same way they are in lua
+    pub fn get(&self, i: usize) -> &Value {
+        self.0.get(i).unwrap_or(&Value::Null)
+    }
+
+    pub fn is_nil(&self, i: usize) -> bool {
+        self.get(i).is_null()
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, i: usize) -> Result<T, serde_json::Error> {
+        T::deserialize(self.get(i))
+    }
+
+    /// For turtle api functions that return `true` or `false, reason`
+    /// # Returns
+    /// The reason if the function did not succeed
+    pub fn success(&self) -> Result<(), String> {
+        match self.get(0) {
+            Value::Bool(true) => Ok(()),
+            _ => Err(match self.get(1) {
+                Value::String(reason) => reason.clone(),
+                _ => self.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for LuaValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Value::Array(self.0.clone()))
+    }
+}
+
+/// # Returns
+/// Id of the request and the message for the client
+pub fn encode_request(lua: &str) -> (u64, String) {
+    let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    let request = serde_json::to_string(&LuaRequest { id, lua })
+        .expect("Lua request is always valid json");
+
+    (id, request)
+}
+
+/// # Returns
+/// Id of the request the reply belongs to (None if the client could not read the request) and its result
+pub fn decode_reply(message: &str) -> Result<(Option<u64>, Result<LuaValues, TurtleRequestError>), TurtleRequestError> {
+    let response: LuaResponse = serde_json::from_str(message).or(Err(TurtleRequestError::InvalidResponse))?;
+
+    let result = match response.ok {
+        true => Ok(LuaValues(response.values)),
+        false => Err(TurtleRequestError::LuaError(response.error.unwrap_or_default())),
+    };
+
+    Ok((response.id, result))
+}
+
+pub fn decode_response(message: &str, id: u64) -> Result<LuaValues, TurtleRequestError> {
+    match decode_reply(message)? {
+        (Some(reply_id), result) if reply_id == id => result,
+        (None, Err(err)) => Err(err),
+        _ => Err(TurtleRequestError::InvalidResponse),
+    }
+}
@@ -0,0 +1,658 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use clap::ValueEnum;
+use serde::Deserialize;
+use shared::{JsonTurtle, world_structure::{ChunkLocation, TurtleWorld}};
+use tempfile::NamedTempFile;
+use tokio::{fs::{File, OpenOptions}, io::AsyncWriteExt};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{config::Config, database::DatabaseActionError};
+
+//The log is compacted into the world file once it is bigger than the world file (but not before it has this size)
+static LOG_COMPACT_MIN_BYTES: u64 = 64 * 1024;
+//Whole palette, only written by older versions
+static LOG_PALETTE_RECORD: u8 = 0;
+static LOG_CHUNK_RECORD: u8 = 1;
+static LOG_PALETTE_ENTRIES_RECORD: u8 = 2;
+static SLED_FILE_NAME: &str = "web_turtle.sled";
+static SLED_PALETTE_KEY: &[u8] = b"palette";
+static SLED_CHUNK_PREFIX: &[u8] = b"chunk";
+
+/// Which [TurtleStorage] the backend uses
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// `<uuid>.json` and `worlds/<name>.world` files in the data dir, changed chunks are appended to `worlds/<name>.log`
+    #[default]
+    Files,
+    /// One embedded database file in the data dir, chunks are saved one by one
+    Sled,
+    /// Nothing is written to disk, everything is lost when the backend stops
+    Memory,
+}
+
+/// Encoded palette and chunks of a world, they are taken under the world lock and written without it
+#[derive(Debug, Clone)]
+pub struct WorldChanges {
+    palette: Bytes,
+    /// Only the palette entries that changed, None if there are none
+    palette_entries: Option<Bytes>,
+    /// None for chunks that are not in the world
+    chunks: Vec<(ChunkLocation, Option<Bytes>)>,
+}
+
+impl WorldChanges {
+    pub fn encode(world: &TurtleWorld, chunks: &[ChunkLocation], palette_ids: &[usize]) -> Result<Self, DatabaseActionError> {
+        let chunks = chunks
+            .iter()
+            .map(|loc| Ok((loc.clone(), world.chunk_to_bytes(loc)?)))
+            .collect::<Result<Vec<(ChunkLocation, Option<Bytes>)>, DatabaseActionError>>()?;
+        let palette_entries = match palette_ids {
+            [] => None,
+            ids => Some(world.palette_entries_to_bytes(ids)?),
+        };
+
+        Ok(Self {
+            palette: world.palette_to_bytes()?,
+            palette_entries,
+            chunks
+        })
+    }
+}
+
+/// Where turtles and worlds are kept between restarts
+#[async_trait]
+pub trait TurtleStorage: Send + Sync + std::fmt::Debug {
+    /// # Returns
+    /// None if the turtle was never saved
+    async fn load_turtle(&self, id: &Uuid) -> Result<Option<JsonTurtle>, DatabaseActionError>;
+
+    async fn save_turtle(&self, turtle: &JsonTurtle) -> Result<(), DatabaseActionError>;
+
+    /// # Returns
+    /// None if the world was never saved
+    async fn load_world(&self, name: &str) -> Result<Option<TurtleWorld>, DatabaseActionError>;
+
+    /// Saves the palette and the changed chunks, chunks that are not in the changes stay as they were saved before
+    async fn save_changes(&self, name: &str, changes: &WorldChanges) -> Result<(), DatabaseActionError>;
+
+    /// Same as [save_changes](TurtleStorage::save_changes), every palette entry counts as changed
+    async fn save_chunks(&self, name: &str, world: &TurtleWorld, chunks: &[ChunkLocation]) -> Result<(), DatabaseActionError> {
+        let palette_ids: Vec<usize> = (0..world.pallete.len()).collect();
+        self.save_changes(name, &WorldChanges::encode(world, chunks, &palette_ids)?).await
+    }
+
+    async fn save_world(&self, name: &str, world: &TurtleWorld) -> Result<(), DatabaseActionError> {
+        let chunks: Vec<ChunkLocation> = world.data.iter().map(|(loc, _)| loc.clone()).collect();
+        self.save_chunks(name, world, &chunks).await
+    }
+}
+
+/// Opens the storage selected in the config
+//this can block but it only runs at startup
+pub fn open_storage(config: &Config) -> Result<Arc<dyn TurtleStorage>, DatabaseActionError> {
+    let data_dir = std::env::current_dir()?.join(&config.data_dir);
+
+    Ok(match config.storage {
+        StorageKind::Files => Arc::new(FileStorage::new(data_dir)?),
+        StorageKind::Sled => Arc::new(SledStorage::new(&data_dir)?),
+        StorageKind::Memory => Arc::new(MemoryStorage::default()),
+    })
+}
+
+/// World name ends up in file paths and database keys
+pub fn validate_world_name(name: &str) -> Result<(), DatabaseActionError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(DatabaseActionError::InvalidWorldName(name.to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct FileStorage {
+    data_dir: PathBuf,
+    worlds_dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(data_dir: PathBuf) -> Result<Self, DatabaseActionError> {
+        let worlds_dir = data_dir.join("worlds");
+        std::fs::create_dir_all(&worlds_dir)?;
+
+        Ok(Self {
+            data_dir,
+            worlds_dir
+        })
+    }
+
+    fn turtle_path(&self, id: &Uuid) -> PathBuf {
+        self.data_dir.join(id.simple().to_string())
+    }
+
+    fn world_path(&self, name: &str) -> Result<PathBuf, DatabaseActionError> {
+        validate_world_name(name)?;
+        Ok(self.worlds_dir.join(name).with_extension("world"))
+    }
+
+    fn log_path(&self, name: &str) -> Result<PathBuf, DatabaseActionError> {
+        validate_world_name(name)?;
+        Ok(self.worlds_dir.join(name).with_extension("log"))
+    }
+
+    /// Appends the changed palette entries and the chunks to the world log, every record is (length: u64, kind: u8, data)
+    /// # Returns
+    /// Length of the log after the append
+    async fn append_log(&self, name: &str, changes: &WorldChanges) -> Result<u64, DatabaseActionError> {
+        let mut records = Vec::new();
+        let mut push_record = |kind: u8, data: &Bytes| {
+            records.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            records.push(kind);
+            records.extend_from_slice(data);
+        };
+
+        if let Some(palette_entries) = &changes.palette_entries {
+            push_record(LOG_PALETTE_ENTRIES_RECORD, palette_entries);
+        }
+        //Chunks are never removed from a world
+        for chunk in changes.chunks.iter().filter_map(|(_, chunk)| chunk.as_ref()) {
+            push_record(LOG_CHUNK_RECORD, chunk);
+        }
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.log_path(name)?)
+            .await?;
+        log.write_all(&records).await?;
+        log.sync_data().await?;
+
+        let log_len = log.metadata().await?.len();
+        //A new log is only found after a crash once its directory entry is on the disk
+        if log_len == records.len() as u64 {
+            sync_dir(&self.worlds_dir).await?;
+        }
+        Ok(log_len)
+    }
+
+    /// Applies the log on top of the world
+    /// # Returns
+    /// False if the log ends with a broken record (the backend stopped while writing it)
+    fn replay_log(world: &mut TurtleWorld, mut log: Bytes) -> bool {
+        while !log.is_empty() {
+            if log.len() < 9 {
+                return false;
+            }
+
+            let len = u64::from_le_bytes(log[..8].try_into().expect("Slice has 8 bytes"));
+            let kind = log[8];
+            let record_len = match usize::try_from(len).ok().and_then(|len| len.checked_add(9)) {
+                Some(record_len) if record_len <= log.len() => record_len,
+                _ => return false,
+            };
+            let data = log.slice(9..record_len);
+            log = log.slice(record_len..);
+
+            let applied = match kind {
+                kind if kind == LOG_PALETTE_RECORD => world.replace_palette_bytes(data).map(|_| ()),
+                kind if kind == LOG_CHUNK_RECORD => world.insert_chunk_bytes(data).map(|_| ()),
+                kind if kind == LOG_PALETTE_ENTRIES_RECORD => world.apply_palette_entries_bytes(data),
+                _ => Err(format!("Unknown log record {kind}").into()),
+            };
+            if applied.is_err() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Writes the data into a temp file and renames it so we never end up with a half written file.
+    /// The file is on the disk when this returns, so whatever it replaces (like the world log) can be removed
+    async fn atomic_write(&self, path: &Path, data: &[u8]) -> Result<(), DatabaseActionError> {
+        let parent = path.parent().unwrap_or(self.data_dir.as_path());
+        let named_tmp_file = NamedTempFile::new_in(parent)?;
+        let (named_tmp_handle, named_tmp_path) = named_tmp_file.into_parts();
+
+        let mut tmp_file = File::from_std(named_tmp_handle);
+        tmp_file.write_all(data).await?;
+        tmp_file.sync_all().await?;
+
+        tokio::fs::rename(named_tmp_path, path).await?;
+        sync_dir(parent).await
+    }
+}
+
+/// Makes renames and new files in the directory survive a power loss
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> Result<(), DatabaseActionError> {
+    File::open(dir).await?.sync_all().await?;
+    Ok(())
+}
+
+//Directories cannot be opened as files on other platforms
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> Result<(), DatabaseActionError> {
+    Ok(())
+}
+
+/// # Returns
+/// None if the file does not exist or is empty
+async fn read_file(path: &Path) -> Result<Option<Vec<u8>>, DatabaseActionError> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) if bytes.is_empty() => Ok(None),
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[async_trait]
+impl TurtleStorage for FileStorage {
+    async fn load_turtle(&self, id: &Uuid) -> Result<Option<JsonTurtle>, DatabaseActionError> {
+        let path = self.turtle_path(id);
+        let json_turtle: JsonTurtle = match read_file(&path.with_extension("json")).await? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => return Ok(None),
+        };
+
+        //Before worlds were shared every turtle had its own <uuid>.world file
+        let legacy_world_path = path.with_extension("world");
+        let world_path = self.world_path(&json_turtle.world)?;
+        if legacy_world_path.try_exists()? {
+            if !world_path.try_exists()? {
+                info!("Using world of turtle {id} as the shared {} world", json_turtle.world);
+                tokio::fs::rename(&legacy_world_path, &world_path).await?;
+            } else {
+                warn!("Turtle {id} has its own world file, it is not merged into the shared {} world", json_turtle.world);
+            }
+        }
+
+        Ok(Some(json_turtle))
+    }
+
+    async fn save_turtle(&self, turtle: &JsonTurtle) -> Result<(), DatabaseActionError> {
+        let json_str = serde_json::to_vec(turtle)?;
+        self.atomic_write(&self.turtle_path(&turtle.uuid).with_extension("json"), &json_str).await
+    }
+
+    async fn load_world(&self, name: &str) -> Result<Option<TurtleWorld>, DatabaseActionError> {
+        let world_file = read_file(&self.world_path(name)?).await?;
+        let log = read_file(&self.log_path(name)?).await?;
+
+        let mut world = match (world_file, &log) {
+            (Some(bytes), _) => TurtleWorld::from_bytes(bytes.into())?,
+            //New worlds only have a log until it is compacted
+            (None, Some(_)) => TurtleWorld::new(),
+            (None, None) => return Ok(None),
+        };
+
+        if let Some(log) = log {
+            if !Self::replay_log(&mut world, log.into()) {
+                //New records would end up after the broken one
+                warn!("Log of world {name} ends with a broken record, it is compacted without it");
+                self.save_world(name, &world).await?;
+            }
+        }
+
+        Ok(Some(world))
+    }
+
+    async fn save_changes(&self, name: &str, changes: &WorldChanges) -> Result<(), DatabaseActionError> {
+        let log_len = self.append_log(name, changes).await?;
+
+        let world_len = match tokio::fs::metadata(self.world_path(name)?).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        if log_len > world_len.max(LOG_COMPACT_MIN_BYTES) {
+            info!("Compacting log of world {name} ({log_len} bytes)");
+            //The world is read back from the disk, the caller does not have to keep it locked while we write
+            if let Some(world) = self.load_world(name).await? {
+                self.save_world(name, &world).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the whole world file and drops the log
+    async fn save_world(&self, name: &str, world: &TurtleWorld) -> Result<(), DatabaseActionError> {
+        let world_bytes = world.to_bytes()?;
+        self.atomic_write(&self.world_path(name)?, &world_bytes).await?;
+
+        match tokio::fs::remove_file(self.log_path(name)?).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SledStorage {
+    db: sled::Db,
+    turtles: sled::Tree,
+}
+
+impl SledStorage {
+    pub fn new(data_dir: &Path) -> Result<Self, DatabaseActionError> {
+        let db = sled::open(data_dir.join(SLED_FILE_NAME))?;
+        let turtles = db.open_tree("turtles")?;
+
+        Ok(Self {
+            db,
+            turtles
+        })
+    }
+
+    /// Every world has its own tree with the palette and one key per chunk
+    fn world_tree(&self, name: &str) -> Result<sled::Tree, DatabaseActionError> {
+        validate_world_name(name)?;
+        Ok(self.db.open_tree(format!("world/{name}"))?)
+    }
+
+    fn chunk_key(loc: &ChunkLocation) -> Vec<u8> {
+        let mut key = Vec::with_capacity(SLED_CHUNK_PREFIX.len() + 9);
+        key.extend_from_slice(SLED_CHUNK_PREFIX);
+        key.extend_from_slice(&loc.x.to_be_bytes());
+        key.extend_from_slice(&loc.y.to_be_bytes());
+        key.extend_from_slice(&loc.z.to_be_bytes());
+        key
+    }
+}
+
+#[async_trait]
+impl TurtleStorage for SledStorage {
+    async fn load_turtle(&self, id: &Uuid) -> Result<Option<JsonTurtle>, DatabaseActionError> {
+        match self.turtles.get(id.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_turtle(&self, turtle: &JsonTurtle) -> Result<(), DatabaseActionError> {
+        self.turtles.insert(turtle.uuid.as_bytes(), serde_json::to_vec(turtle)?)?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    async fn load_world(&self, name: &str) -> Result<Option<TurtleWorld>, DatabaseActionError> {
+        let tree = self.world_tree(name)?;
+        let palette = match tree.get(SLED_PALETTE_KEY)? {
+            Some(palette) => Bytes::copy_from_slice(&palette),
+            None => return Ok(None),
+        };
+
+        let chunks = tree
+            .scan_prefix(SLED_CHUNK_PREFIX)
+            .values()
+            .map(|chunk| chunk.map(|chunk| Bytes::copy_from_slice(&chunk)))
+            .collect::<Result<Vec<Bytes>, sled::Error>>()?;
+
+        Ok(Some(TurtleWorld::from_parts(palette, chunks)?))
+    }
+
+    async fn save_changes(&self, name: &str, changes: &WorldChanges) -> Result<(), DatabaseActionError> {
+        let tree = self.world_tree(name)?;
+
+        //Palette and chunks are written together so a chunk never refers to a palette entry that was not saved
+        let mut batch = sled::Batch::default();
+        batch.insert(SLED_PALETTE_KEY, changes.palette.as_ref());
+        for (loc, chunk) in &changes.chunks {
+            match chunk {
+                Some(chunk) => batch.insert(Self::chunk_key(loc), chunk.as_ref()),
+                None => batch.remove(Self::chunk_key(loc)),
+            }
+        }
+
+        tree.apply_batch(batch)?;
+        self.db.flush_async().await?;
+        Ok(())
+    }
+}
+
+/// Keeps the encoded data the same way a real store would, so loading still goes through the world format
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    turtles: std::sync::Mutex<HashMap<Uuid, Vec<u8>>>,
+    worlds: std::sync::Mutex<HashMap<String, MemoryWorld>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryWorld {
+    palette: Bytes,
+    chunks: HashMap<ChunkLocation, Bytes>,
+}
+
+#[async_trait]
+impl TurtleStorage for MemoryStorage {
+    async fn load_turtle(&self, id: &Uuid) -> Result<Option<JsonTurtle>, DatabaseActionError> {
+        let turtles = self.turtles.lock().expect("Memory storage lock is poisoned");
+        match turtles.get(id) {
+            Some(bytes) => Ok(Some(serde_json::from_slice(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_turtle(&self, turtle: &JsonTurtle) -> Result<(), DatabaseActionError> {
+        let bytes = serde_json::to_vec(turtle)?;
+        self.turtles.lock().expect("Memory storage lock is poisoned").insert(turtle.uuid, bytes);
+        Ok(())
+    }
+
+    async fn load_world(&self, name: &str) -> Result<Option<TurtleWorld>, DatabaseActionError> {
+        validate_world_name(name)?;
+        let worlds = self.worlds.lock().expect("Memory storage lock is poisoned");
+        match worlds.get(name) {
+            Some(world) => Ok(Some(TurtleWorld::from_parts(world.palette.clone(), world.chunks.values().cloned())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_changes(&self, name: &str, changes: &WorldChanges) -> Result<(), DatabaseActionError> {
+        validate_world_name(name)?;
+
+        let mut worlds = self.worlds.lock().expect("Memory storage lock is poisoned");
+        let stored = worlds.entry(name.to_string()).or_default();
+        stored.palette = changes.palette.clone();
+        for (loc, chunk) in &changes.chunks {
+            match chunk {
+                Some(chunk) => stored.chunks.insert(loc.clone(), chunk.clone()),
+                None => stored.chunks.remove(loc),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::{JsonTurtle, JsonTurtleDirection, TurtleFuel, world_structure::{ChunkLocation, TurtleVoxel, TurtleWorld}};
+    use uuid::Uuid;
+
+    use super::{FileStorage, MemoryStorage, SledStorage, TurtleStorage, WorldChanges, LOG_COMPACT_MIN_BYTES};
+
+    fn test_turtle() -> JsonTurtle {
+        JsonTurtle {
+            uuid: Uuid::new_v4(),
+            x: 1,
+            y: -2,
+            z: 3,
+            rotation: JsonTurtleDirection::Left,
+            fuel: TurtleFuel::Limited { level: 10, limit: 20 },
+            world: "overworld".to_string(),
+            gps_located: true,
+            online: false,
+            last_seen: None,
+            excavation: None,
+        }
+    }
+
+    /// # Returns
+    /// Chunk of the block
+    fn set_block(world: &mut TurtleWorld, name: &str, (x, y, z): (i32, i32, i32)) -> ChunkLocation {
+        let (loc, ..) = TurtleWorld::get_chunk_loc_from_global_xyz(x, y, z).expect("Block is outside of the world");
+        let (palette, chunks) = world.get_fields_mut();
+        let (id, _) = palette.get_pallete_index(name);
+        chunks.force_get_mut_chunk_by_loc(&loc).update_voxel_by_global_xyz(x, y, z, |voxel| {
+            *voxel = TurtleVoxel::id(id as u16);
+            Ok(())
+        }).expect("Cannot set block");
+        loc
+    }
+
+    fn test_world() -> TurtleWorld {
+        let mut world = TurtleWorld::new();
+        set_block(&mut world, "minecraft:stone", (0, 0, 0));
+        set_block(&mut world, "minecraft:dirt", (-20, 40, 7));
+        world
+    }
+
+    async fn assert_round_trip(storage: &dyn TurtleStorage) {
+        let turtle = test_turtle();
+        assert_eq!(storage.load_turtle(&turtle.uuid).await.expect("Cannot load turtle"), None);
+        storage.save_turtle(&turtle).await.expect("Cannot save turtle");
+        assert_eq!(storage.load_turtle(&turtle.uuid).await.expect("Cannot load turtle"), Some(turtle));
+
+        assert!(storage.load_world("overworld").await.expect("Cannot load world").is_none());
+        assert!(storage.load_world("../overworld").await.is_err());
+
+        let mut world = test_world();
+        storage.save_world("overworld", &world).await.expect("Cannot save world");
+        assert!(storage.load_world("overworld").await.expect("Cannot load world") == Some(test_world()));
+
+        //Only the changed chunks are given, the rest has to stay
+        let changed = set_block(&mut world, "minecraft:furnace", (1, 0, 0));
+        let new = set_block(&mut world, "minecraft:sand", (100, 0, 100));
+        storage.save_chunks("overworld", &world, &[changed, new]).await.expect("Cannot save chunks");
+        assert!(storage.load_world("overworld").await.expect("Cannot load world") == Some(world));
+    }
+
+    #[tokio::test]
+    async fn test_file_round_trip() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        assert_round_trip(&FileStorage::new(dir.path().to_path_buf()).expect("Cannot open storage")).await;
+    }
+
+    #[tokio::test]
+    async fn test_sled_round_trip() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        assert_round_trip(&SledStorage::new(dir.path()).expect("Cannot open storage")).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_round_trip() {
+        assert_round_trip(&MemoryStorage::default()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sled_reopen() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let mut world = test_world();
+        {
+            let storage = SledStorage::new(dir.path()).expect("Cannot open storage");
+            storage.save_world("overworld", &world).await.expect("Cannot save world");
+            let loc = set_block(&mut world, "minecraft:furnace", (2, 0, 0));
+            storage.save_chunks("overworld", &world, &[loc]).await.expect("Cannot save chunks");
+        }
+
+        let storage = SledStorage::new(dir.path()).expect("Cannot reopen storage");
+        assert!(storage.load_world("overworld").await.expect("Cannot load world") == Some(world));
+    }
+
+    #[tokio::test]
+    async fn test_file_log_replay() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let mut world = test_world();
+        {
+            let storage = FileStorage::new(dir.path().to_path_buf()).expect("Cannot open storage");
+            storage.save_world("overworld", &world).await.expect("Cannot save world");
+            for (i, name) in ["minecraft:furnace", "minecraft:sand", "minecraft:furnace"].into_iter().enumerate() {
+                let loc = set_block(&mut world, name, (i as i32, 0, 0));
+                storage.save_chunks("overworld", &world, &[loc]).await.expect("Cannot save chunks");
+            }
+            assert!(storage.log_path("overworld").unwrap().exists());
+        }
+
+        //Restart, the world file is old and the changes are only in the log
+        let storage = FileStorage::new(dir.path().to_path_buf()).expect("Cannot reopen storage");
+        assert!(storage.load_world("overworld").await.expect("Cannot load world").as_ref() == Some(&world));
+
+        //The backend stopped while writing a record
+        let log_path = storage.log_path("overworld").unwrap();
+        let mut log = std::fs::read(&log_path).expect("Cannot read log");
+        log.extend_from_slice(&100u64.to_le_bytes());
+        log.push(1);
+        log.extend_from_slice(&[0; 10]);
+        std::fs::write(&log_path, log).expect("Cannot write log");
+
+        let loaded = storage.load_world("overworld").await.expect("Cannot load world with a broken log");
+        assert!(loaded.as_ref() == Some(&world));
+        assert!(!log_path.exists(), "Broken log was not compacted");
+        assert!(storage.load_world("overworld").await.expect("Cannot load world") == loaded);
+    }
+
+    #[tokio::test]
+    async fn test_file_log_palette_entries() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let storage = FileStorage::new(dir.path().to_path_buf()).expect("Cannot open storage");
+        let log_path = storage.log_path("overworld").unwrap();
+        let mut world = test_world();
+        storage.save_world("overworld", &world).await.expect("Cannot save world");
+        world.pallete.take_dirty();
+
+        //Only the new entry is logged, the rest of the palette is in the world file
+        let loc = set_block(&mut world, "minecraft:furnace", (1, 0, 0));
+        let palette_ids = world.pallete.take_dirty();
+        assert_eq!(palette_ids.len(), 1);
+        let changes = WorldChanges::encode(&world, &[loc], &palette_ids).expect("Cannot encode changes");
+        storage.save_changes("overworld", &changes).await.expect("Cannot save changes");
+        let first_len = std::fs::metadata(&log_path).expect("Cannot read log metadata").len();
+
+        let loc = set_block(&mut world, "minecraft:furnace", (2, 0, 0));
+        assert!(world.pallete.take_dirty().is_empty());
+        let changes = WorldChanges::encode(&world, &[loc], &[]).expect("Cannot encode changes");
+        storage.save_changes("overworld", &changes).await.expect("Cannot save changes");
+        let second_len = std::fs::metadata(&log_path).expect("Cannot read log metadata").len();
+        assert!(second_len - first_len < first_len, "Palette was logged again");
+
+        assert!(storage.load_world("overworld").await.expect("Cannot load world") == Some(world));
+    }
+
+    #[tokio::test]
+    async fn test_file_log_compaction() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let storage = FileStorage::new(dir.path().to_path_buf()).expect("Cannot open storage");
+        let log_path = storage.log_path("overworld").unwrap();
+        let mut world = test_world();
+        storage.save_world("overworld", &world).await.expect("Cannot save world");
+
+        let mut log_len = 0;
+        let mut compacted = false;
+        for i in 0..10_000 {
+            let name = if i % 2 == 0 { "minecraft:furnace" } else { "minecraft:sand" };
+            let loc = set_block(&mut world, name, (i % 16, (i / 16) % 16, 0));
+            storage.save_chunks("overworld", &world, &[loc]).await.expect("Cannot save chunks");
+
+            if !log_path.exists() {
+                compacted = true;
+                break;
+            }
+            log_len = std::fs::metadata(&log_path).expect("Cannot read log metadata").len();
+        }
+
+        assert!(compacted, "Log was never compacted");
+        assert!(log_len > LOG_COMPACT_MIN_BYTES / 2);
+        assert!(storage.load_world("overworld").await.expect("Cannot load world").as_ref() == Some(&world));
+
+        //The log starts again on top of the rewritten world file
+        let loc = set_block(&mut world, "minecraft:dirt", (0, 0, 1));
+        storage.save_chunks("overworld", &world, &[loc]).await.expect("Cannot save chunks");
+        assert!(log_path.exists());
+        assert!(storage.load_world("overworld").await.expect("Cannot load world") == Some(world));
+    }
+}
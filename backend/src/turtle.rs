@@ -1,19 +1,317 @@
-use std::{time::Duration, num::TryFromIntError};
+use std::{collections::HashSet, future::Future, time::{Duration, SystemTime, UNIX_EPOCH}, num::TryFromIntError, sync::{Arc, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}}};
 
+use serde::Deserialize;
 use serde_json::Value;
-use shared::{JsonTurtleDirection, WorldChange, WorldChangeAction, WorldChangeDeleteBlock, TurtleBlock, WorldChangeUpdateBlock, WorldChangeNewBlock, DestroyBlockResponse, world_structure::{TurtleWorld, TurtleVoxel}, WorldChangePaletteEnum};
+use shared::{ExcavationJob, ExcavationRequest, ExcavationState, ExplorationProgress, ExplorationRequest, ExplorationState, JsonTurtle, JsonTurtleDirection, TurtleEvent, TurtleFuel, WorldChange, WorldChangeAction, WorldChangeDeleteBlock, TurtleBlock, WorldChangeUpdateBlock, WorldChangeNewBlock, DestroyBlockResponse, PlaceBlockResponse, TurtleInventoryItem, TransferItemsRequest, ItemsSideRequest, RefuelRequest, TurtlePositionRequest, world_structure::{TurtleVoxel, TurtleWorld}, WorldChangePaletteEnum};
 use thiserror::Error;
-use tokio::{sync::{oneshot, mpsc::{self, Sender}}, time::timeout};
-use tracing::error;
-use uuid::Uuid;
+use tokio::{sync::{broadcast, oneshot, watch, Mutex, OwnedMutexGuard, mpsc}, time::timeout};
+use tracing::{error, warn, info, debug};
 
-use crate::database::{DatabaseActionError, TurtleDatabase};
+use crate::{config::config, database::{DatabaseActionError, TurtleDatabase, WorldRegistry}, pathfinding::{distance, find_frontier, find_path, BlockPosition, Region}, protocol::LuaValues};
 
-//Lua inspect logic
-static INSPECT_DOWN_PAYLOAD: &str = "local has_block, data = turtle.inspectDown() return textutils.serialiseJSON(data)";
-static INSPECT_FORWARD_PAYLOAD: &str = "local has_block, data = turtle.inspect() return textutils.serialiseJSON(data)";
-static INSPECT_UP_PAYLOAD: &str = "local has_block, data = turtle.inspectUp() return textutils.serialiseJSON(data)";
+//Lua inspect logic, returns has_block and the block data (or the reason there is no block)
+static INSPECT_DOWN_PAYLOAD: &str = "return turtle.inspectDown()";
+static INSPECT_FORWARD_PAYLOAD: &str = "return turtle.inspect()";
+static INSPECT_UP_PAYLOAD: &str = "return turtle.inspectUp()";
+static INSPECT_BACK_PAYLOAD: &str = "turtle.turnRight() turtle.turnRight() local has_block, data = turtle.inspect() turtle.turnLeft() turtle.turnLeft() return has_block, data";
+static INSPECT_LEFT_PAYLOAD: &str = "turtle.turnLeft() local has_block, data = turtle.inspect() turtle.turnRight() return has_block, data";
+static INSPECT_RIGHT_PAYLOAD: &str = "turtle.turnRight() local has_block, data = turtle.inspect() turtle.turnLeft() return has_block, data";
+//Turns right through every heading and back to the start, returns has_block and data of the right, back and left side
+static INSPECT_AROUND_PAYLOAD: &str = "turtle.turnRight() local right, right_data = turtle.inspect() turtle.turnRight() local back, back_data = turtle.inspect() turtle.turnRight() local left, left_data = turtle.inspect() turtle.turnRight() return right, right_data, back, back_data, left, left_data";
+//getFuelLevel returns "unlimited" when fuel is disabled in the server config
+static GET_FUEL_PAYLOAD: &str = "return turtle.getFuelLevel(), turtle.getFuelLimit()";
+//Empty slots are json_null so the array always has 16 elements
+static GET_INVENTORY_PAYLOAD: &str = "local items = {} for i = 1, 16 do items[i] = turtle.getItemDetail(i) or textutils.json_null end return turtle.getSelectedSlot(), items";
 static DESTROY_BLOCK_FRONT: &str = "return turtle.dig()";
+static DESTROY_BLOCK_UP: &str = "return turtle.digUp()";
+static DESTROY_BLOCK_DOWN: &str = "return turtle.digDown()";
+//Sides and back are dug by turning, digging and turning back so the turtle rotation does not change
+static DESTROY_BLOCK_BACK: &str = "turtle.turnRight() turtle.turnRight() local ok, err = turtle.dig() turtle.turnLeft() turtle.turnLeft() return ok, err";
+static DESTROY_BLOCK_LEFT: &str = "turtle.turnLeft() local ok, err = turtle.dig() turtle.turnRight() return ok, err";
+static DESTROY_BLOCK_RIGHT: &str = "turtle.turnRight() local ok, err = turtle.dig() turtle.turnLeft() return ok, err";
+//A goto gives up after the turtle was blocked this many times
+static MAX_GOTO_REPLANS: u32 = 32;
+//Sides that are inspected after every move
+static SCAN_SIDES: [JsonTurtleDirection; 3] = [JsonTurtleDirection::Down, JsonTurtleDirection::Forward, JsonTurtleDirection::Up];
+//In the order Turtle::scan_full inspects them
+static ALL_SIDES: [JsonTurtleDirection; 6] = [
+    JsonTurtleDirection::Down,
+    JsonTurtleDirection::Forward,
+    JsonTurtleDirection::Up,
+    JsonTurtleDirection::Right,
+    JsonTurtleDirection::Backward,
+    JsonTurtleDirection::Left,
+];
+//Fuel kept on top of the way home, gravel and detours make the way back longer
+static EXCAVATION_FUEL_RESERVE: i64 = 64;
+//A stop digs its own block and the blocks above and below it, every one of them can need a new slot
+static EXCAVATION_FREE_SLOTS: usize = 3;
+
+//gps.locate returns nil when there are not enough gps hosts in range
+static GPS_LOCATE_PAYLOAD: &str = "return gps.locate(2)";
+//Moves one block (turning right if blocked) and locates again, the turtle goes back to where it started if it can
+static GPS_HEADING_PAYLOAD: &str = "for turns = 0, 3 do if turtle.forward() then local x, y, z = gps.locate(2) local back = turtle.back() for i = 1, turns do turtle.turnLeft() end if not x then return nil end return x, y, z, turns, back end turtle.turnRight() end return nil, \"Cannot move\"";
+
+
+/// Everything the turtle socket can run, every command is one lua payload
+#[derive(Debug, Clone)]
+pub enum TurtleCommand {
+    /// Returns `true` or `false, reason`
+    Move(JsonTurtleDirection),
+    /// Returns `true` or `false, reason`
+    Dig(JsonTurtleDirection),
+    /// Returns `false, reason` or `true` and the placed block (turtle.inspect data)
+    Place { side: JsonTurtleDirection, slot: u8 },
+    /// Returns what turtle.inspect returns
+    Inspect(JsonTurtleDirection),
+    RawLua(String),
+}
+
+impl TurtleCommand {
+    pub fn payload(&self) -> String {
+        match self {
+            TurtleCommand::Move(direction) => {
+                let function = match direction {
+                    JsonTurtleDirection::Forward => "forward",
+                    JsonTurtleDirection::Backward => "back",
+                    JsonTurtleDirection::Right => "turnRight",
+                    JsonTurtleDirection::Left => "turnLeft",
+                    JsonTurtleDirection::Up => "up",
+                    JsonTurtleDirection::Down => "down",
+                };
+                format!("return turtle.{function}()")
+            },
+            TurtleCommand::Dig(side) => match side {
+                JsonTurtleDirection::Forward => DESTROY_BLOCK_FRONT,
+                JsonTurtleDirection::Backward => DESTROY_BLOCK_BACK,
+                JsonTurtleDirection::Left => DESTROY_BLOCK_LEFT,
+                JsonTurtleDirection::Right => DESTROY_BLOCK_RIGHT,
+                JsonTurtleDirection::Up => DESTROY_BLOCK_UP,
+                JsonTurtleDirection::Down => DESTROY_BLOCK_DOWN,
+            }.to_string(),
+            TurtleCommand::Place { side, slot } => place_block_payload(side, *slot),
+            TurtleCommand::Inspect(side) => match side {
+                JsonTurtleDirection::Forward => INSPECT_FORWARD_PAYLOAD,
+                JsonTurtleDirection::Backward => INSPECT_BACK_PAYLOAD,
+                JsonTurtleDirection::Left => INSPECT_LEFT_PAYLOAD,
+                JsonTurtleDirection::Right => INSPECT_RIGHT_PAYLOAD,
+                JsonTurtleDirection::Up => INSPECT_UP_PAYLOAD,
+                JsonTurtleDirection::Down => INSPECT_DOWN_PAYLOAD,
+            }.to_string(),
+            TurtleCommand::RawLua(lua) => lua.clone(),
+        }
+    }
+
+    /// How long the socket waits for the turtle to answer
+    pub fn timeout(&self) -> Duration {
+        match self {
+            TurtleCommand::Dig(_) => config().timeouts.dig(),
+            _ => config().timeouts.command(),
+        }
+    }
+}
+
+fn parse_fuel(response: &LuaValues) -> Result<TurtleFuel, TurtleFuelError> {
+    let invalid = || TurtleFuelError::InvalidTurtleResponse(response.to_string());
+
+    if response.get(0).as_str() == Some("unlimited") {
+        return Ok(TurtleFuel::Unlimited);
+    }
+
+    let level = response.get(0).as_i64().ok_or_else(invalid)?;
+    let limit = response.get(1).as_i64().ok_or_else(invalid)?;
+
+    Ok(TurtleFuel::Limited { level, limit })
+}
+
+/// # Returns
+/// All 16 slots of the [GET_INVENTORY_PAYLOAD] response, index 0 is slot 1. Empty slots are None
+fn parse_inventory(response: &LuaValues) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleGetInventoryError> {
+    let selected: usize = response.parse(0)?;
+    let lua_items: Vec<Option<LuaInventoryItem>> = response.parse(1)?;
+
+    let mut items: Vec<Option<TurtleInventoryItem>> = lua_items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            item.map(|item| TurtleInventoryItem {
+                name: item.name,
+                count: item.count,
+                selected: i + 1 == selected,
+            })
+        })
+        .collect();
+    items.resize_with(16, || None);
+
+    Ok(items)
+}
+
+struct LuaGpsPosition {
+    x: f64,
+    y: f64,
+    z: f64,
+    turns: u8,
+    /// False if the turtle could not move back after locating
+    back: bool,
+}
+
+impl LuaGpsPosition {
+    /// Reads `x, y, z`, the optional number of turns and whether the turtle moved back
+    fn parse(response: &LuaValues) -> Result<Option<Self>, TurtleGpsError> {
+        if response.is_nil(0) {
+            return match response.get(1).as_str() {
+                Some(reason) => Err(TurtleGpsError::InvalidTurtleResponse(reason.to_string())),
+                None => Ok(None),
+            };
+        }
+
+        let invalid = || TurtleGpsError::InvalidTurtleResponse(response.to_string());
+        Ok(Some(Self {
+            x: response.get(0).as_f64().ok_or_else(invalid)?,
+            y: response.get(1).as_f64().ok_or_else(invalid)?,
+            z: response.get(2).as_f64().ok_or_else(invalid)?,
+            turns: response.parse::<Option<u8>>(3).map_err(|_| invalid())?.unwrap_or(0),
+            back: response.parse::<Option<bool>>(4).map_err(|_| invalid())?.unwrap_or(true),
+        }))
+    }
+
+    fn block_position(&self) -> (i32, i32, i32) {
+        (self.x.floor() as i32, self.y.floor() as i32, self.z.floor() as i32)
+    }
+}
+
+/// # Returns
+/// Position from [GPS_LOCATE_PAYLOAD] or None if there is no gps in range
+fn parse_gps_position(response: &LuaValues) -> Result<Option<(i32, i32, i32)>, TurtleGpsError> {
+    Ok(LuaGpsPosition::parse(response)?.map(|position| position.block_position()))
+}
+
+/// # Returns
+/// Position and rotation of the turtle, based on the [GPS_HEADING_PAYLOAD] response and the position before moving.
+/// The position is the located one if the turtle could not move back
+fn parse_gps_heading(response: &LuaValues, position: (i32, i32, i32)) -> Result<((i32, i32, i32), JsonTurtleDirection), TurtleGpsError> {
+    let moved = LuaGpsPosition::parse(response)?
+        .ok_or(TurtleGpsError::NoGps)?;
+    let (x, y, z) = moved.block_position();
+
+    let mut rotation = JsonTurtleDirection::from_forward_diff(x - position.0, z - position.2)
+        .ok_or_else(|| TurtleGpsError::InvalidTurtleResponse(response.to_string()))?;
+    //The turtle turned right before moving
+    for _ in 0..moved.turns {
+        rotation.rotate_self(&JsonTurtleDirection::Left);
+    }
+
+    if moved.back {
+        Ok((position, rotation))
+    } else {
+        Ok(((x, y, z), rotation))
+    }
+}
+
+/// # Returns
+/// The block from the turtle.inspect result, None if there is no block
+fn parse_inspected_block(response: &LuaValues) -> Result<Option<TurtleBlock>, TurtleWorldScanError> {
+    match response.get(0) {
+        Value::Bool(true) => Ok(Some(response.parse::<TurtleBlock>(1)?)),
+        Value::Bool(false) => Ok(None),
+        _ => Err(TurtleWorldScanError::InvalidTurtleResponse(response.to_string())),
+    }
+}
+
+/// Writes the inspected block into the world, None means there is no block and it is recorded as air
+/// # Returns
+/// The change that has to be sent to the client or None if the world did not change
+fn record_inspected_block(world: &mut TurtleWorld, block: Option<TurtleBlock>, x: i32, y: i32, z: i32) -> Result<Option<WorldChange>, TurtleWorldScanError> {
+    let (loc, _, _, _) = TurtleWorld::get_chunk_loc_from_global_xyz(x, y, z)?;
+    //Read first so blocks we already know do not make their chunk dirty
+    let db_block = world.get_voxel_by_global_xyz(x, y, z);
+
+    let (palette, chunks) = world.get_fields_mut();
+
+    let Some(block) = block else {
+        if db_block.is_some_and(|data| data.is_air()) {
+            return Ok(None);
+        }
+
+        //Unknown blocks become air too, so the client knows the block was inspected
+        let chunk = chunks.force_get_mut_chunk_by_loc(&loc);
+        chunk.update_voxel_by_global_xyz(x, y, z, |voxel| {
+            *voxel = TurtleVoxel::air();
+            Ok(())
+        })?;
+        let action = WorldChangeAction::Delete(WorldChangeDeleteBlock {});
+        return Ok(Some(WorldChange { x, y, z, action }));
+    };
+
+    let name = block.palette_name();
+    let tags = block.tags();
+
+    //Tags that changed are a palette change too, writing the voxel again makes sure the palette is saved with its chunk
+    let (palette_id, changed) = palette.get_pallete_index_with_tags(&name, &tags);
+    if !changed && db_block.is_some_and(|data| data.is_solid() && data.id as usize == palette_id) {
+        return Ok(None);
+    }
+
+    let chunk = chunks.force_get_mut_chunk_by_loc(&loc);
+    chunk.update_voxel_by_global_xyz(x, y, z, |voxel| {
+        voxel.id = palette_id.try_into()?;
+        Ok(())
+    })?;
+
+    let palette_enum = if changed {
+        WorldChangePaletteEnum::Insert { i: palette_id, name, tags }
+    } else {
+        WorldChangePaletteEnum::GetOld { i: palette_id }
+    };
+
+    let action = match db_block {
+        Some(db_block) if db_block.is_solid() => WorldChangeAction::Update(WorldChangeUpdateBlock {
+            palette: palette_enum,
+        }),
+        _ => WorldChangeAction::New(WorldChangeNewBlock {
+            palette: palette_enum,
+        }),
+    };
+
+    Ok(Some(WorldChange { x, y, z, action }))
+}
+
+fn validate_slot(slot: u8) -> Result<u8, TurtleInventoryActionError> {
+    match slot {
+        1..=16 => Ok(slot),
+        _ => Err(TurtleInventoryActionError::InvalidSlot(slot))
+    }
+}
+
+/// Turtle api functions like drop and suck only work forward, up and down
+fn vertical_suffix(side: &JsonTurtleDirection) -> Result<&'static str, TurtleInventoryActionError> {
+    match side {
+        JsonTurtleDirection::Forward => Ok(""),
+        JsonTurtleDirection::Up => Ok("Up"),
+        JsonTurtleDirection::Down => Ok("Down"),
+        side => Err(TurtleInventoryActionError::InvalidSide(side.clone()))
+    }
+}
+
+/// Lua argument list for an optional item count
+fn lua_count(count: Option<u32>) -> String {
+    count.map(|count| count.to_string()).unwrap_or_default()
+}
+
+/// Selects the slot, places the block and inspects it so we know what got placed.
+/// Sides and back are handled by turning the same way as [DESTROY_BLOCK_BACK]
+fn place_block_payload(side: &JsonTurtleDirection, slot: u8) -> String {
+    let (turn, turn_back, suffix) = match side {
+        JsonTurtleDirection::Forward => ("", "", ""),
+        JsonTurtleDirection::Backward => ("turtle.turnRight() turtle.turnRight()", "turtle.turnLeft() turtle.turnLeft()", ""),
+        JsonTurtleDirection::Left => ("turtle.turnLeft()", "turtle.turnRight()", ""),
+        JsonTurtleDirection::Right => ("turtle.turnRight()", "turtle.turnLeft()", ""),
+        JsonTurtleDirection::Up => ("", "", "Up"),
+        JsonTurtleDirection::Down => ("", "", "Down"),
+    };
+
+    format!("turtle.select({slot}) {turn} local ok, err = turtle.place{suffix}() local has_block, data = turtle.inspect{suffix}() {turn_back} if not ok then return false, err end return true, data")
+}
 
 #[derive(Error, Debug)]
 pub enum TurtleRequestError {
@@ -28,19 +326,60 @@ pub enum TurtleRequestError {
     #[error("Cannot send request")]
     RequestSendError,
     #[error("Response recv error")]
-    ResponseRecvError
+    ResponseRecvError,
+    #[error("Cancelled, the turtle was stopped")]
+    Cancelled,
+    #[error("Turtle is offline")]
+    Offline,
+    #[error("Lua error ({0})")]
+    LuaError(String),
+    #[error("Turtle action failed ({0})")]
+    ActionFailed(#[from] tokio::task::JoinError),
 }
 
 #[derive(Error, Debug)]
 pub enum TurtleMoveError {
     #[error("Request error")]
     RequestError(#[from] TurtleRequestError),
-    #[error("Cannot move turtle")]
-    CannotMove,
-    #[error("Invalid turtle response ({0})")]
+    #[error("Cannot move turtle ({0})")]
+    CannotMove(String),
+    #[error("Turtle is out of fuel")]
+    OutOfFuel,
+    #[error("Cannot get fuel level")]
+    FuelError(#[from] TurtleFuelError),
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseActionError),
+    //Boxed, the scan error can contain a move error
+    #[error("Cannot scan the world")]
+    ScanError(Box<TurtleWorldScanError>),
+}
+
+impl From<TurtleWorldScanError> for TurtleMoveError {
+    fn from(err: TurtleWorldScanError) -> Self {
+        TurtleMoveError::ScanError(Box::new(err))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TurtleFuelError {
+    #[error("Request error")]
+    RequestError(#[from] TurtleRequestError),
+    #[error("Invalid fuel response ({0})")]
     InvalidTurtleResponse(String),
 }
 
+#[derive(Error, Debug)]
+pub enum TurtleGpsError {
+    #[error("No gps in range")]
+    NoGps,
+    #[error("Invalid gps response ({0})")]
+    InvalidTurtleResponse(String),
+    #[error("Request error")]
+    RequestError(#[from] TurtleRequestError),
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseActionError),
+}
+
 #[derive(Error, Debug)]
 pub enum TurtleWorldScanError {
     #[error("Request error")]
@@ -49,26 +388,16 @@ pub enum TurtleWorldScanError {
     JsonError(#[from] serde_json::error::Error),
     #[error("Cannot rotate turtle")]
     TurtleRotationError(#[from] TurtleMoveError),
-    #[error("Turtle does not have ID")]
-    InvalidTurtle,
     #[error(transparent)]
     DynamicError(#[from] Box<dyn std::error::Error + Send + Sync>),
-    #[error("Corrupted world {0}")]
-    CorruptedWorld(String),
     #[error("Cannot convent int types")]
     IntError(#[from] TryFromIntError),
-    #[error("Unreachable reached ({0})")]
-    UnreachableReached(String),
+    #[error("Invalid turtle response ({0})")]
+    InvalidTurtleResponse(String),
     #[error(transparent)]
     DatabaseError(#[from] DatabaseActionError)
 }
 
-#[derive(Error, Debug)]
-pub enum TurtleWorldShowError {
-    #[error("Turtle does not contain ID")]
-    InvalidTurtleError
-}
-
 #[derive(Error, Debug)] 
 pub enum TurtleDestroyBlockError{
     #[error(transparent)]
@@ -77,254 +406,1056 @@ pub enum TurtleDestroyBlockError{
     RequestError(#[from] TurtleRequestError),
     #[error("Unexpected response ({0})")]
     UnexpectedResponse(String),
-    #[error("Not yet implemented")]
-    NotImplemented,
     #[error(transparent)]
     DynamicError(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
+#[derive(Error, Debug)]
+pub enum TurtleGotoError {
+    #[error("No known path to the target")]
+    NoPath,
+    #[error("Turtle was blocked {0} times")]
+    TooManyReplans(u32),
+    #[error("Path step {0:?} is not next to the turtle")]
+    InvalidStep(BlockPosition),
+    #[error(transparent)]
+    MoveError(#[from] TurtleMoveError),
+    #[error(transparent)]
+    DestroyBlockError(#[from] TurtleDestroyBlockError),
+    #[error(transparent)]
+    ScanError(#[from] TurtleWorldScanError),
+}
+
+#[derive(Error, Debug)]
+pub enum TurtleExploreError {
+    #[error("Turtle is outside of the explored region")]
+    StartOutsideRegion,
+    #[error(transparent)]
+    GotoError(#[from] TurtleGotoError),
+    #[error(transparent)]
+    ScanError(#[from] TurtleWorldScanError),
+}
+
+#[derive(Error, Debug)]
+pub enum TurtleExcavationError {
+    #[error("Turtle has no excavation job")]
+    NoJob,
+    #[error("Turtle already has an unfinished excavation job")]
+    JobExists,
+    #[error("Excavation area is too large")]
+    TooLarge,
+    #[error("Excavation job is {0:?}")]
+    InvalidState(ExcavationState),
+    #[error(transparent)]
+    GotoError(#[from] TurtleGotoError),
+    #[error(transparent)]
+    DestroyBlockError(#[from] TurtleDestroyBlockError),
+    #[error(transparent)]
+    InventoryError(#[from] TurtleGetInventoryError),
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseActionError),
+}
+
+#[derive(Error, Debug)]
+pub enum TurtlePlaceBlockError {
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseActionError),
+    #[error("Request error")]
+    RequestError(#[from] TurtleRequestError),
+    #[error("Invalid inventory slot ({0})")]
+    InvalidSlot(u8),
+    #[error("Cannot place block ({0})")]
+    CannotPlace(String),
+    #[error("Cannot update world")]
+    WorldError(#[from] TurtleWorldScanError),
+}
+
 #[derive(Error, Debug)]
 pub enum TurtleGetInventoryError {
     #[error("Request error")]
     RequestError(#[from] TurtleRequestError),
-    #[error("Turtle response is not valid json")]
-    TurtleResponseNotJson,
-    #[error("Returned name does not contain \":\" symbol (cannot split)")]
-    InvalidName
+    #[error("Invalid turtle response")]
+    InvalidTurtleResponse(#[from] serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum TurtleInventoryActionError {
+    #[error("Request error")]
+    RequestError(#[from] TurtleRequestError),
+    #[error("Invalid inventory slot ({0})")]
+    InvalidSlot(u8),
+    #[error("Invalid side ({0:?})")]
+    InvalidSide(JsonTurtleDirection),
+    #[error("Turtle refused the action ({0})")]
+    ActionFailed(String),
+    #[error("Cannot get inventory after the action")]
+    GetInventoryError(#[from] TurtleGetInventoryError),
+    #[error("Cannot get fuel level")]
+    FuelError(#[from] TurtleFuelError),
+}
+
+#[derive(Deserialize)]
+struct LuaInventoryItem {
+    name: String,
+    count: i64
 }
 
+
+/// Commands that are sent to the turtle together, the replies are matched by request id
 pub struct TurtleAsyncRequest {
-    pub request: String,
-    pub response: oneshot::Sender<Result<String, TurtleRequestError>>
+    pub commands: Vec<TurtleCommand>,
+    /// Stop generation of the action that sent the command, see [TurtleControl::stop]
+    pub generation: u64,
+    pub response: oneshot::Sender<Result<Vec<LuaValues>, TurtleRequestError>>
+}
+
+/// Socket the turtle commands are sent to
+#[derive(Debug)]
+struct TurtleConnection {
+    id: u64,
+    requests: mpsc::Sender<TurtleAsyncRequest>,
+    /// Dropped when a newer socket takes over, the old socket then closes and fails its requests
+    _replaced: oneshot::Sender<()>,
+}
+
+/// Shared by the turtle, its socket and the http handlers so it can be used without locking the turtle
+#[derive(Debug, Default)]
+pub struct TurtleControl {
+    pending_actions: AtomicUsize,
+    stop_generation: AtomicU64,
+    connection: std::sync::Mutex<Option<TurtleConnection>>,
+    next_connection_id: AtomicU64,
+    /// Unix time in seconds, 0 if the turtle was never seen
+    last_seen: AtomicU64,
+    /// True while a background job drives the turtle, see [crate::excavation::spawn_excavation]
+    job_running: AtomicBool,
+}
+
+impl TurtleControl {
+    /// Actions that are running or waiting for the turtle
+    pub fn queue_depth(&self) -> usize {
+        self.pending_actions.load(Ordering::SeqCst)
+    }
+
+    /// Cancels every waiting action and every command the running action sends from now on.
+    /// The command the turtle is running in game cannot be stopped
+    pub fn stop(&self) {
+        self.stop_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.stop_generation.load(Ordering::SeqCst)
+    }
+
+    /// Sends the turtle commands to a new socket, the previous socket (if any) is told to close
+    /// # Returns
+    /// Connection id for [TurtleControl::detach] and a receiver that resolves once a newer socket takes over
+    pub fn attach(&self, requests: mpsc::Sender<TurtleAsyncRequest>) -> (u64, oneshot::Receiver<()>) {
+        let id = self.next_connection_id.fetch_add(1, Ordering::SeqCst);
+        let (replaced_tx, replaced_rx) = oneshot::channel();
+
+        *self.connection.lock().expect("Cannot lock turtle connection") = Some(TurtleConnection {
+            id,
+            requests,
+            _replaced: replaced_tx,
+        });
+        self.touch();
+
+        (id, replaced_rx)
+    }
+
+    /// Marks the turtle offline unless a newer socket already took over
+    /// # Returns
+    /// True if the turtle went offline
+    pub fn detach(&self, id: u64) -> bool {
+        let mut guard = self.connection.lock().expect("Cannot lock turtle connection");
+        match &*guard {
+            Some(connection) if connection.id == id => {
+                *guard = None;
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.connection.lock().expect("Cannot lock turtle connection").is_some()
+    }
+
+    fn requests(&self) -> Option<mpsc::Sender<TurtleAsyncRequest>> {
+        self.connection.lock().expect("Cannot lock turtle connection")
+            .as_ref()
+            .map(|connection| connection.requests.clone())
+    }
+
+    /// Remembers that the turtle answered just now
+    pub fn touch(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        self.last_seen.store(now, Ordering::SeqCst);
+    }
+
+    pub fn last_seen(&self) -> Option<u64> {
+        match self.last_seen.load(Ordering::SeqCst) {
+            0 => None,
+            time => Some(time)
+        }
+    }
+
+    /// # Returns
+    /// False if another job task is already running
+    pub fn start_job(&self) -> bool {
+        self.job_running.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    pub fn finish_job(&self) {
+        self.job_running.store(false, Ordering::SeqCst);
+    }
+
+    /// Connection state is not stored in the database, it is added to the turtle data when it is sent out
+    pub fn fill_connection(&self, turtle: &mut JsonTurtle) {
+        turtle.online = self.is_online();
+        turtle.last_seen = self.last_seen();
+    }
+}
+
+/// Counts an action in [TurtleControl::queue_depth] until it is dropped
+struct PendingAction(Arc<TurtleControl>);
+
+impl PendingAction {
+    fn new(control: Arc<TurtleControl>) -> Self {
+        control.pending_actions.fetch_add(1, Ordering::SeqCst);
+        Self(control)
+    }
+}
+
+impl Drop for PendingAction {
+    fn drop(&mut self) {
+        self.0.pending_actions.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 #[derive(Debug)]
 pub struct Turtle {
-    request_queue: mpsc::Sender<TurtleAsyncRequest>,
+    events: broadcast::Sender<TurtleEvent>,
+    data_snapshot: watch::Sender<JsonTurtle>,
+    control: Arc<TurtleControl>,
+    /// Stop generation of the running action
+    action_generation: u64,
     pub database: TurtleDatabase
 }
 
+/// What the turtle registry keeps, every turtle is locked on its own
+#[derive(Clone)]
+pub struct TurtleHandle {
+    pub turtle: Arc<Mutex<Turtle>>,
+    /// Latest turtle data, readable while the turtle is busy with a command
+    pub data: watch::Receiver<JsonTurtle>,
+    pub control: Arc<TurtleControl>,
+}
+
+impl TurtleHandle {
+    pub fn new(turtle: Turtle) -> Self {
+        Self {
+            data: turtle.data_snapshot.subscribe(),
+            control: turtle.control.clone(),
+            turtle: Arc::new(Mutex::new(turtle)),
+        }
+    }
+
+    /// Latest turtle data with the connection state
+    pub fn json(&self) -> JsonTurtle {
+        let mut turtle = self.data.borrow().clone();
+        self.control.fill_connection(&mut turtle);
+        turtle
+    }
+
+    /// Waits for the turtle and runs the action on its own task.
+    /// Waiting is cancelled when the caller is dropped (for example the http client disconnected),
+    /// but a started action always finishes so the database never misses a move that happened in game
+    pub async fn run<F, Fut, R>(&self, action: F) -> Result<R, TurtleRequestError>
+    where
+        F: FnOnce(OwnedMutexGuard<Turtle>) -> Fut,
+        Fut: Future<Output = R> + Send + 'static,
+        R: Send + 'static
+    {
+        let pending = PendingAction::new(self.control.clone());
+        let generation = self.control.generation();
+
+        let mut turtle = self.turtle.clone().lock_owned().await;
+        if self.control.generation() != generation {
+            return Err(TurtleRequestError::Cancelled);
+        }
+        turtle.action_generation = generation;
+
+        let action = action(turtle);
+        let result = tokio::spawn(async move {
+            let result = action.await;
+            drop(pending);
+            result
+        }).await?;
+
+        Ok(result)
+    }
+}
+
 impl Turtle {
 
-    pub fn new(uuid: Uuid, database: TurtleDatabase, tx: Sender<TurtleAsyncRequest>) -> Self {
+    pub fn new(database: TurtleDatabase, events: broadcast::Sender<TurtleEvent>) -> Self {
         Self {
-            request_queue: tx,
+            events,
+            data_snapshot: watch::channel(database.turtle_data.clone()).0,
+            control: Default::default(),
+            action_generation: 0,
             database,
         }
     }
 
-    pub async fn command(&mut self, command: &str) -> Result<String, TurtleRequestError> {
-        let (tx, rx) = oneshot::channel::<Result<String, TurtleRequestError>>();
+    /// Tells everyone (frontends and the turtle list) that the turtle data changed
+    fn publish_update(&self) {
+        self.data_snapshot.send_replace(self.database.turtle_data.clone());
+
+        let mut turtle = self.database.turtle_data.clone();
+        self.control.fill_connection(&mut turtle);
+        self.emit(TurtleEvent::TurtleUpdate(turtle));
+    }
+
+    /// Sends the event to every listening frontend, it is fine if there are none
+    fn emit(&self, event: TurtleEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn emit_world_change(&self, world: &str, change: &WorldChange) {
+        self.emit(TurtleEvent::WorldChange {
+            world: world.to_string(),
+            change: change.clone(),
+        });
+    }
+
+    pub async fn command(&mut self, command: &str) -> Result<LuaValues, TurtleRequestError> {
+        self.send(TurtleCommand::RawLua(command.to_string())).await
+    }
+
+    pub async fn send(&mut self, command: TurtleCommand) -> Result<LuaValues, TurtleRequestError> {
+        self.batch(vec![command]).await?
+            .pop()
+            .ok_or(TurtleRequestError::InvalidResponse)
+    }
+
+    /// Sends independent commands in one round trip, the turtle still runs them one after another
+    /// # Returns
+    /// Response of every command in order, the first failed command fails the whole batch
+    pub async fn batch(&mut self, commands: Vec<TurtleCommand>) -> Result<Vec<LuaValues>, TurtleRequestError> {
+        let request_queue = self.control.requests().ok_or(TurtleRequestError::Offline)?;
+        let (tx, rx) = oneshot::channel::<Result<Vec<LuaValues>, TurtleRequestError>>();
 
         let request = TurtleAsyncRequest {
-            request: command.to_string(),
+            commands,
+            generation: self.action_generation,
             response: tx,
         };
 
-        match timeout(Duration::from_secs(10), self.request_queue.send(request)).await {
+        match timeout(config().timeouts.queue(), request_queue.send(request)).await {
             Ok(val) => val.or(Err(TurtleRequestError::RequestSendError))?,
             Err(_) => return Err(TurtleRequestError::TimeOut),
         }
 
-        match timeout(Duration::from_secs(10), rx).await {
-            Ok(val) => return val.or(Err(TurtleRequestError::ResponseRecvError))?,
-            Err(_) => return Err(TurtleRequestError::TimeOut),
-        }
+        //The socket times out every command on its own
+        rx.await.or(Err(TurtleRequestError::ResponseRecvError))?
     }
 
-    pub async fn move_turtle(&mut self, direction: JsonTurtleDirection) -> Result<(), TurtleMoveError> {
-        let command = match direction {
-            JsonTurtleDirection::Forward => {
-                "local a, b = turtle.forward() return a"
-            },
-            JsonTurtleDirection::Backward => "local a, b = turtle.back() return a",
-            JsonTurtleDirection::Right => "local a, b = turtle.turnRight() return a",
-            JsonTurtleDirection::Left => "local a, b = turtle.turnLeft() return a"
+    /// Moves the turtle and scans the blocks around its new position in one round trip
+    pub async fn move_and_scan(&mut self, direction: JsonTurtleDirection) -> Result<Vec<WorldChange>, TurtleMoveError> {
+        let responses = self.move_with(direction, Self::scan_commands(&SCAN_SIDES)).await?;
+        Ok(self.record_scan(&SCAN_SIDES, &responses).await?)
+    }
+
+    /// Moves the turtle, the extra commands are sent in the same batch and run after the move
+    /// # Returns
+    /// Responses of the extra commands
+    async fn move_with(&mut self, direction: JsonTurtleDirection, extra: Vec<TurtleCommand>) -> Result<Vec<LuaValues>, TurtleMoveError> {
+        let is_turn = matches!(direction, JsonTurtleDirection::Right | JsonTurtleDirection::Left);
+
+        //Do not bother the turtle if we know it cannot move
+        if !is_turn && self.is_out_of_fuel() {
+            //Someone might have refueled it in game
+            self.refresh_fuel().await?;
+            if self.is_out_of_fuel() {
+                return Err(TurtleMoveError::OutOfFuel);
+            }
+        }
+
+        let mut commands = vec![
+            TurtleCommand::Move(direction.clone()),
+            TurtleCommand::RawLua(GET_FUEL_PAYLOAD.to_string()),
+        ];
+        commands.extend(extra);
+
+        let mut responses = self.batch(commands).await?.into_iter();
+        let (result, fuel) = match (responses.next(), responses.next()) {
+            (Some(result), Some(fuel)) => (result, fuel),
+            _ => return Err(TurtleRequestError::InvalidResponse.into())
         };
 
-        let result = self.command(command).await?;
-        match result.as_str() {
-            "true" => {
+        match result.success() {
+            Ok(()) => {
                 match direction {
                     JsonTurtleDirection::Right | JsonTurtleDirection::Left => {
-                        self.database.turtle_data.rotation.rotate_self(&direction); 
+                        self.database.turtle_data.rotation.rotate_self(&direction);
                     },
                     direction => {
                         let (x_diff, y_diff, z_diff) = direction.to_turtle_move_diff(&self.database.turtle_data.rotation);
                         self.database.turtle_data.x += x_diff;
                         self.database.turtle_data.y += y_diff;
                         self.database.turtle_data.z += z_diff;
+                        self.record_occupied_block().await;
                     }
                 };
-                return Ok(()); 
+                self.database.turtle_data.fuel = parse_fuel(&fuel)?;
+                self.database.save().await?;
+                self.publish_update();
+                Ok(responses.collect())
+            },
+            //Reason returned by turtle movement functions
+            Err(reason) if reason == "Out of fuel" => {
+                if let TurtleFuel::Limited { level, .. } = &mut self.database.turtle_data.fuel {
+                    *level = 0;
+                }
+                self.database.save().await?;
+                self.publish_update();
+                Err(TurtleMoveError::OutOfFuel)
             },
-            "false" => return Err(TurtleMoveError::CannotMove),
-            _ => return Err(TurtleMoveError::InvalidTurtleResponse(result))
+            Err(reason) => Err(TurtleMoveError::CannotMove(reason))
         }
     }
 
-    pub async fn scan_world_changes(&mut self) -> Result<Vec<WorldChange>, TurtleWorldScanError> {
-        let x = self.database.turtle_data.x;
-        let y = self.database.turtle_data.y;
-        let z = self.database.turtle_data.z;
-
-        let blocks: Vec<(String, i32, i32, i32)> = vec![
-            (self.command(INSPECT_DOWN_PAYLOAD).await?, x, y - 1, z),
-            {
-                let (x_diff, y_diff, z_diff) = JsonTurtleDirection::Forward.to_turtle_move_diff(&self.database.turtle_data.rotation);
-                let forward = self.command(INSPECT_FORWARD_PAYLOAD).await?;
-                (forward, x + x_diff, y + y_diff, z + z_diff)
-            },
-            (self.command(INSPECT_UP_PAYLOAD).await?, x, y + 1, z),
-        ];
+    /// Moves the turtle into the shared world coordinates (and possibly into another world)
+    /// without moving it in game
+    pub async fn set_position(&mut self, request: TurtlePositionRequest, worlds: &WorldRegistry) -> Result<(), DatabaseActionError> {
+        if let JsonTurtleDirection::Up | JsonTurtleDirection::Down = request.rotation {
+            return Err(DatabaseActionError::DynamicError("Turtle cannot face up or down".into()));
+        }
 
-         let changes = blocks
-            .into_iter()
-            .map(|(block, x, y, z)| {
-                let (loc, local_x, local_y, local_z) = TurtleWorld::get_chunk_loc_from_global_xyz(x, y, z)?;
-              
-                let (palette, chunks) = self.database.world.get_fields_mut();
-                let chunk = chunks.get_mut_chunk_by_loc(&loc);
-                let db_block: Result<Option<&mut TurtleVoxel>, TurtleWorldScanError> = match chunk {
-                    None => Ok(None),
-                    Some(chunk) => {
-                        let voxel = match chunk.get_mut_block_by_local_xyz(local_x, local_y, local_z) {
-                            Some(v) => v,
-                            None => {
-                                return Err(TurtleWorldScanError::UnreachableReached("lineralize error".into()))
-                            }
-                        };
-
-                        Ok(Some(voxel))
-                    }
-                };
+        if let Some(world_name) = request.world {
+            self.database.world = worlds.get_or_load(&world_name).await?;
+            self.database.turtle_data.world = world_name;
+        }
 
-                let db_block = db_block?;
+        let turtle_data = &mut self.database.turtle_data;
+        turtle_data.x = request.x;
+        turtle_data.y = request.y;
+        turtle_data.z = request.z;
+        turtle_data.rotation = request.rotation;
 
-                if block == "\"No block to inspect\"" {
-                    //this is ugly, but it works
-                    if db_block.is_none() || db_block.as_ref().is_some_and(|data| data.id == 0)
-                    {
-                        return Ok(None);
-                    }
+        self.database.save().await?;
+        self.publish_update();
+        Ok(())
+    }
 
-                    tracing::warn!("Attempt to remvoe block. {} {}", db_block.is_none(), db_block.is_some_and(|data| data.id == 0));
-                    chunks.remove_global_block_by_xyz(x, y, z)?;
-                    let action = WorldChangeAction::Delete(WorldChangeDeleteBlock {});
-                    return Ok(Some(WorldChange { x, y, z, action }));
-                }
+    fn is_out_of_fuel(&self) -> bool {
+        matches!(self.database.turtle_data.fuel, TurtleFuel::Limited { level, .. } if level <= 0)
+    }
 
-                let name = serde_json::from_str::<TurtleBlock>(&block)?.name;
+    /// Asks the turtle for its fuel level and stores it in the turtle data
+    pub async fn refresh_fuel(&mut self) -> Result<TurtleFuel, TurtleFuelError> {
+        let response = self.command(GET_FUEL_PAYLOAD).await?;
+        let fuel = parse_fuel(&response)?;
+        self.database.turtle_data.fuel = fuel.clone();
 
-                let action = match db_block {
-                    Some(db_block) if db_block.id != 0 => {
-                        let db_block_name = palette.get_pallete_from_id(db_block.id).ok_or(TurtleWorldScanError::CorruptedWorld("Pallete does not containt voxel id".into()))?;
-                        if name.as_str() == &*db_block_name {
-                            return Ok(None);
-                        }
+        Ok(fuel)
+    }
+
+    /// Locates the turtle with gps, the gps position wins over the stored one.
+    /// The heading is only checked when the stored position is not trusted, it moves the turtle and costs fuel
+    async fn locate_with_gps(&mut self) -> Result<(), TurtleGpsError> {
+        let uuid = self.database.turtle_data.uuid;
+        let Some(position) = parse_gps_position(&self.command(GPS_LOCATE_PAYLOAD).await?)? else {
+            debug!("No gps in range of turtle {uuid}");
+            return Ok(());
+        };
 
-                        let (pallete_id, new_id) = palette.get_pallete_index(&name);
-                        db_block.id = pallete_id.try_into()?;
+        let turtle_data = &self.database.turtle_data;
+        let stored_position = (turtle_data.x, turtle_data.y, turtle_data.z);
+        if turtle_data.gps_located && stored_position == position {
+            debug!("Turtle {uuid} is at its stored position {position:?}");
+            return Ok(());
+        }
 
-                        let palette_enum = if new_id {
-                            WorldChangePaletteEnum::Insert { i: pallete_id, name }
-                        } else {
-                            WorldChangePaletteEnum::GetOld { i: pallete_id }
-                        };
+        if turtle_data.gps_located {
+            warn!("Turtle {uuid} drifted from {stored_position:?} to {position:?}");
+        } else {
+            info!("Turtle {uuid} located with gps at {position:?}");
+        }
 
-                        WorldChangeAction::Update(WorldChangeUpdateBlock {
-                            palette: palette_enum,
-                        })
+        let (position, rotation) = parse_gps_heading(&self.command(GPS_HEADING_PAYLOAD).await?, position)?;
+        let turtle_data = &mut self.database.turtle_data;
+        if turtle_data.gps_located && turtle_data.rotation != rotation {
+            warn!("Turtle {uuid} rotation drifted from {:?} to {rotation:?}", turtle_data.rotation);
+        }
 
-                    }
-                    _ => {
-                        let (palette_id, new_id) = palette.get_pallete_index(&name);
-
-                        //TODO: Get chunks and set new voxel
-                        let chunk = chunks.force_get_mut_chunk_by_loc(&loc);
-                        chunk.update_voxel_by_global_xyz(x, y, z, |voxel| {
-                            voxel.id = palette_id.try_into()?;
-                            Ok(())
-                        })?;
-
-
-                        let palette_enum = if new_id {
-                            WorldChangePaletteEnum::Insert { i: palette_id, name }
-                        } else {
-                            WorldChangePaletteEnum::GetOld { i: palette_id }
-                        };
-
-                        WorldChangeAction::New(WorldChangeNewBlock {
-                            palette: palette_enum,
-                        })
-                    }
-                };
+        (turtle_data.x, turtle_data.y, turtle_data.z) = position;
+        turtle_data.rotation = rotation;
+        turtle_data.gps_located = true;
+        self.database.save().await?;
 
-                Ok(Some(WorldChange { x, y, z, action }))
+        Ok(())
+    }
+
+    /// Refreshes what could have changed in game while the turtle was disconnected
+    pub async fn resume(&mut self) -> Result<(), TurtleFuelError> {
+        //The turtle could have been moved by hand while it was offline
+        if config().gps {
+            if let Err(err) = self.locate_with_gps().await {
+                warn!("Cannot locate turtle {} with gps: {err}", self.database.turtle_data.uuid);
+            }
+        }
+
+        self.refresh_fuel().await?;
+        self.publish_update();
+        Ok(())
+    }
+
+    /// Inspects the given sides, see [Turtle::record_scan]
+    fn scan_commands(sides: &[JsonTurtleDirection]) -> Vec<TurtleCommand> {
+        sides.iter().cloned().map(TurtleCommand::Inspect).collect()
+    }
+
+    /// # Returns
+    /// Global position of the block on every given side of the turtle
+    fn side_positions(&self, sides: &[JsonTurtleDirection]) -> Vec<BlockPosition> {
+        let (x, y, z) = self.position();
+        sides.iter()
+            .map(|side| {
+                let (x_diff, y_diff, z_diff) = side.to_turtle_side_diff(&self.database.turtle_data.rotation);
+                (x + x_diff, y + y_diff, z + z_diff)
             })
+            .collect()
+    }
+
+    /// Inspects below, in front of and above the turtle
+    pub async fn scan_world_changes(&mut self) -> Result<Vec<WorldChange>, TurtleWorldScanError> {
+        let responses = self.batch(Self::scan_commands(&SCAN_SIDES)).await?;
+        self.record_scan(&SCAN_SIDES, &responses).await
+    }
+
+    /// Inspects all six sides in one round trip. The sides are inspected by a single lua chunk that turns
+    /// back to where the turtle started, so the rotation in the database never changes
+    pub async fn scan_full(&mut self) -> Result<Vec<WorldChange>, TurtleWorldScanError> {
+        let mut commands = Self::scan_commands(&SCAN_SIDES);
+        commands.push(TurtleCommand::RawLua(INSPECT_AROUND_PAYLOAD.to_string()));
+
+        let mut responses = self.batch(commands).await?;
+        //Split the sides into one response each, the same way separate inspects would return them
+        let around = responses.pop().unwrap_or_default();
+        responses.extend((0..3).map(|i| LuaValues(vec![around.get(i * 2).clone(), around.get(i * 2 + 1).clone()])));
+        self.record_scan(&ALL_SIDES, &responses).await
+    }
+
+    /// The turtle is standing in the block so it has to be air
+    async fn record_occupied_block(&mut self) {
+        let (x, y, z) = (self.database.turtle_data.x, self.database.turtle_data.y, self.database.turtle_data.z);
+        let mut world = self.database.world.lock().await;
+        match record_inspected_block(world.world_mut(), None, x, y, z) {
+            Ok(Some(change)) => self.emit_world_change(world.name(), &change),
+            Ok(None) => {},
+            //The move itself succeeded, a world we cannot write to should not fail it
+            Err(err) => error!("Cannot record the position of turtle {}: {err}", self.database.turtle_data.uuid),
+        }
+    }
+
+    /// Writes the responses of [Turtle::scan_commands] into the world around the current position
+    async fn record_scan(&mut self, sides: &[JsonTurtleDirection], responses: &[LuaValues]) -> Result<Vec<WorldChange>, TurtleWorldScanError> {
+        let positions = self.side_positions(sides);
+        if responses.len() != positions.len() {
+            return Err(TurtleWorldScanError::InvalidTurtleResponse(format!("{} scan responses", responses.len())));
+        }
+
+        let mut world = self.database.world.lock().await;
+        let changes = responses
+            .iter()
+            .zip(positions)
+            .map(|(response, (x, y, z))| record_inspected_block(world.world_mut(), parse_inspected_block(response)?, x, y, z))
             .filter_map(Result::transpose)
             .collect::<Result<Vec<WorldChange>, TurtleWorldScanError>>()?;
 
-        if changes.len() != 0 {
-            self.database.save().await?;
+        for change in &changes {
+            self.emit_world_change(world.name(), change);
         }
 
         Ok(changes)
     }
 
     pub async fn destroy_block(&mut self, side: JsonTurtleDirection) -> Result<DestroyBlockResponse, TurtleDestroyBlockError> {
-        let payload = match side {
-            JsonTurtleDirection::Forward => DESTROY_BLOCK_FRONT,
-            _ => return Err(TurtleDestroyBlockError::NotImplemented)
-        };
-
-        let response = self.command(payload).await?;
+        let response = self.send(TurtleCommand::Dig(side.clone())).await?;
 
-        match response.as_str() {
-            "true" => {
-                let (x_diff, y_dif, z_diff) = side.to_turtle_move_diff(&self.database.turtle_data.rotation);
+        match response.get(0) {
+            Value::Bool(true) => {
+                let (x_diff, y_dif, z_diff) = side.to_turtle_side_diff(&self.database.turtle_data.rotation);
                 let (x, y, z) = (self.database.turtle_data.x + x_diff, self.database.turtle_data.y + y_dif, self.database.turtle_data.z + z_diff);
 
                 //BlockData::delete_by_xyz(connection, x, y, z)?;
-                let (_, world) = self.database.world.get_fields_mut();
+                let mut world_database = self.database.world.lock().await;
+                let (_, world) = world_database.world_mut().get_fields_mut();
                 let (loc, _, _, _) = TurtleWorld::get_chunk_loc_from_global_xyz(x, y, z)?;
 
                 let chunk = world.force_get_mut_chunk_by_loc(&loc);
                 chunk.update_voxel_by_global_xyz(x, y, z, |voxel| {
-                    voxel.id = 0;
+                    *voxel = TurtleVoxel::air();
                     Ok(())
                 })?;
 
-                self.database.save().await?;
+                let change = WorldChange {
+                    x,
+                    y,
+                    z,
+                    action: WorldChangeAction::Delete(WorldChangeDeleteBlock()),
+                };
+                self.emit_world_change(world_database.name(), &change);
+
+                Ok(DestroyBlockResponse {
+                    change: Some(change),
+                })
+            }
+            //Nothing to dig or the block cannot be broken
+            Value::Bool(false) => Ok(DestroyBlockResponse { change: None }),
+            _ => Err(TurtleDestroyBlockError::UnexpectedResponse(response.to_string()))
+        }
+    }
+
+    fn position(&self) -> BlockPosition {
+        (self.database.turtle_data.x, self.database.turtle_data.y, self.database.turtle_data.z)
+    }
+
+    /// Moves the turtle to the target along a path through the known world (see [find_path]),
+    /// the path is planned again every time the turtle is blocked. Blocks are only dug inside the dig region
+    async fn goto(&mut self, target: BlockPosition, dig_region: Option<&Region>) -> Result<(), TurtleGotoError> {
+        //Blocks the turtle could not get into even though the world says it can
+        let mut blocked = HashSet::new();
+        let mut replans = 0;
+
+        while self.position() != target {
+            let path = {
+                let world = self.database.world.lock().await;
+                find_path(world.world(), self.position(), target, dig_region, &blocked)
+            };
+            let path = path.ok_or(TurtleGotoError::NoPath)?;
+
+            for next in path {
+                if !self.goto_step(next, dig_region).await? {
+                    blocked.insert(next);
+                    replans += 1;
+                    if replans > MAX_GOTO_REPLANS {
+                        return Err(TurtleGotoError::TooManyReplans(replans));
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns the turtle with the fewest turns, every turn scans the block in front of the turtle
+    async fn face(&mut self, rotation: JsonTurtleDirection) -> Result<(), TurtleMoveError> {
+        let current = self.database.turtle_data.rotation.clone();
+        let mut left = current.clone();
+        left.rotate_self(&JsonTurtleDirection::Left);
+
+        let turns = if current == rotation {
+            vec![]
+        } else if left == rotation {
+            vec![JsonTurtleDirection::Left]
+        } else {
+            let mut right = current;
+            right.rotate_self(&JsonTurtleDirection::Right);
+            if right == rotation {
+                vec![JsonTurtleDirection::Right]
+            } else {
+                vec![JsonTurtleDirection::Right, JsonTurtleDirection::Right]
+            }
+        };
+
+        for turn in turns {
+            self.move_and_scan(turn).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves into a block next to the turtle, digging it first when it is inside the dig region
+    /// # Returns
+    /// False if the turtle could not get there, what was in the way is recorded in the world
+    async fn goto_step(&mut self, next: BlockPosition, dig_region: Option<&Region>) -> Result<bool, TurtleGotoError> {
+        let (x, y, z) = self.position();
+        let direction = match (next.0 - x, next.1 - y, next.2 - z) {
+            (0, 1, 0) => JsonTurtleDirection::Up,
+            (0, -1, 0) => JsonTurtleDirection::Down,
+            (x_diff, 0, z_diff) => {
+                let rotation = JsonTurtleDirection::from_forward_diff(x_diff, z_diff).ok_or(TurtleGotoError::InvalidStep(next))?;
+                self.face(rotation).await?;
+                JsonTurtleDirection::Forward
+            },
+            _ => return Err(TurtleGotoError::InvalidStep(next)),
+        };
+
+        let allow_digging = dig_region.is_some_and(|region| region.contains(next));
+        let known_block = {
+            let world = self.database.world.lock().await;
+            world.world().get_voxel_by_global_xyz(next.0, next.1, next.2).is_some_and(|voxel| voxel.is_solid())
+        };
+        if allow_digging && known_block {
+            self.destroy_block(direction.clone()).await?;
+        }
+
+        match self.move_and_scan(direction.clone()).await {
+            Ok(_) => return Ok(true),
+            Err(TurtleMoveError::CannotMove(_)) => {},
+            Err(err) => return Err(err.into()),
+        }
+
+        //The world did not know about the block
+        if allow_digging && self.destroy_block(direction.clone()).await?.change.is_some() {
+            match self.move_and_scan(direction).await {
+                Ok(_) => return Ok(true),
+                Err(TurtleMoveError::CannotMove(_)) => {},
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        self.scan_world_changes().await?;
+        Ok(false)
+    }
+
+    fn emit_exploration_progress(&self, progress: &ExplorationProgress) {
+        self.emit(TurtleEvent::ExplorationProgress {
+            uuid: self.database.turtle_data.uuid,
+            progress: progress.clone(),
+        });
+    }
+
+    /// Goes to the closest unknown block of the region (see [find_frontier]) and inspects every side there,
+    /// until the region is explored or the moves are used up. Progress is sent as [TurtleEvent::ExplorationProgress]
+    pub async fn explore(&mut self, request: ExplorationRequest) -> Result<ExplorationProgress, TurtleExploreError> {
+        let region = Region::new(request.from, request.to);
+        if !region.contains(self.position()) {
+            return Err(TurtleExploreError::StartOutsideRegion);
+        }
+
+        let mut progress = ExplorationProgress {
+            moves: 0,
+            max_moves: request.max_moves,
+            known: 0,
+            state: ExplorationState::Exploring,
+        };
+        let mut known = HashSet::new();
+
+        let result = self.explore_region(&region, &mut known, &mut progress).await;
+        progress.known = known.len();
+        progress.state = match &result {
+            Ok(state) => state.clone(),
+            Err(err) => ExplorationState::Failed { reason: err.to_string() },
+        };
+        self.emit_exploration_progress(&progress);
+
+        result.map(|_| progress)
+    }
+
+    async fn explore_region(&mut self, region: &Region, known: &mut HashSet<BlockPosition>, progress: &mut ExplorationProgress) -> Result<ExplorationState, TurtleExploreError> {
+        //Blocks the turtle could not get into even though the world says it can
+        let mut blocked = HashSet::new();
+        //Frontiers without a path, they stay unknown
+        let mut unreachable = HashSet::new();
+
+        self.inspect_around(known).await?;
+        loop {
+            progress.known = known.len();
+            self.emit_exploration_progress(progress);
+
+            let (target, path) = {
+                let world = self.database.world.lock().await;
+                match find_frontier(world.world(), region, self.position(), &unreachable) {
+                    Some(target) => (target, find_path(world.world(), self.position(), target, None, &blocked)),
+                    None => return Ok(ExplorationState::Explored),
+                }
+            };
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    unreachable.insert(target);
+                    continue;
+                }
+            };
+
+            for next in path {
+                if progress.moves >= progress.max_moves {
+                    return Ok(ExplorationState::OutOfMoves);
+                }
+
+                let moved = self.goto_step(next, None).await?;
+                //Either way the block was inspected from where the turtle is now
+                known.insert(self.position());
+                known.extend(self.side_positions(&SCAN_SIDES));
+                if !moved {
+                    blocked.insert(next);
+                    break;
+                }
+                progress.moves += 1;
+            }
+
+            if self.position() == target {
+                self.inspect_around(known).await?;
+            }
+        }
+    }
+
+    /// Inspects every side of the turtle and remembers them as known
+    async fn inspect_around(&mut self, known: &mut HashSet<BlockPosition>) -> Result<(), TurtleWorldScanError> {
+        self.scan_full().await?;
+        known.insert(self.position());
+        known.extend(self.side_positions(&ALL_SIDES));
+        Ok(())
+    }
+
+    async fn save_excavation(&mut self, job: ExcavationJob) -> Result<ExcavationJob, DatabaseActionError> {
+        self.database.turtle_data.excavation = Some(job.clone());
+        self.database.save().await?;
+        self.publish_update();
+        Ok(job)
+    }
+
+    fn excavation(&self) -> Result<ExcavationJob, TurtleExcavationError> {
+        self.database.turtle_data.excavation.clone().ok_or(TurtleExcavationError::NoJob)
+    }
 
-                return Ok(DestroyBlockResponse {
-                    change: Some(WorldChange {
-                        x,
-                        y,
-                        z,
-                        action: WorldChangeAction::Delete(WorldChangeDeleteBlock()),
-                    }),
-                });
+    /// Replaces a finished, cancelled or failed job, the job is run by [crate::excavation::spawn_excavation]
+    pub async fn start_excavation(&mut self, request: ExcavationRequest) -> Result<ExcavationJob, TurtleExcavationError> {
+        if let Some(job) = &self.database.turtle_data.excavation {
+            if let ExcavationState::Running | ExcavationState::Paused { .. } = job.state {
+                return Err(TurtleExcavationError::JobExists);
             }
-            "false" => return Ok(DestroyBlockResponse { change: None }),
-            _ => return Err(TurtleDestroyBlockError::UnexpectedResponse(response))
         }
+
+        let home = request.home.unwrap_or(self.position());
+        let job = ExcavationJob::new(request.from, request.to, home).ok_or(TurtleExcavationError::TooLarge)?;
+        Ok(self.save_excavation(job).await?)
+    }
+
+    /// Paused jobs only get a new reason
+    pub async fn pause_excavation(&mut self, reason: &str) -> Result<ExcavationJob, TurtleExcavationError> {
+        let mut job = self.excavation()?;
+        match job.state {
+            ExcavationState::Running | ExcavationState::Paused { .. } => job.state = ExcavationState::Paused { reason: reason.to_string() },
+            state => return Err(TurtleExcavationError::InvalidState(state)),
+        }
+
+        Ok(self.save_excavation(job).await?)
+    }
+
+    pub async fn resume_excavation(&mut self) -> Result<ExcavationJob, TurtleExcavationError> {
+        let mut job = self.excavation()?;
+        match job.state {
+            ExcavationState::Paused { .. } => job.state = ExcavationState::Running,
+            state => return Err(TurtleExcavationError::InvalidState(state)),
+        }
+
+        Ok(self.save_excavation(job).await?)
+    }
+
+    pub async fn cancel_excavation(&mut self) -> Result<ExcavationJob, TurtleExcavationError> {
+        let mut job = self.excavation()?;
+        match job.state {
+            ExcavationState::Running | ExcavationState::Paused { .. } => job.state = ExcavationState::Cancelled,
+            state => return Err(TurtleExcavationError::InvalidState(state)),
+        }
+
+        Ok(self.save_excavation(job).await?)
+    }
+
+    /// Digs the next stop of the running excavation job, errors end up in the job state
+    /// # Returns
+    /// False once the job is not running anymore or the turtle went offline
+    pub async fn excavation_step(&mut self) -> bool {
+        let job = match &self.database.turtle_data.excavation {
+            Some(job) if job.state == ExcavationState::Running => job.clone(),
+            _ => return false,
+        };
+
+        let err = match self.dig_next_stop(job.clone()).await {
+            Ok(running) => return running,
+            Err(err) => err,
+        };
+
+        let state = if !self.control.is_online() {
+            //Started again when the turtle connects
+            return false;
+        } else if self.control.generation() != self.action_generation {
+            ExcavationState::Paused { reason: "Turtle was stopped".to_string() }
+        } else {
+            ExcavationState::Failed { reason: err.to_string() }
+        };
+
+        //Progress of the failed stop was not saved, it is dug again on resume
+        let job = ExcavationJob { state, ..self.excavation().unwrap_or(job) };
+        if let Err(err) = self.save_excavation(job).await {
+            error!("Cannot save excavation job of turtle {}: {err}", self.database.turtle_data.uuid);
+        }
+        false
     }
 
-    pub async fn get_inventory(&mut self) -> Result<Vec<String>, TurtleGetInventoryError>{
-        let mut res = Vec::<String>::with_capacity(16);
+    async fn dig_next_stop(&mut self, mut job: ExcavationJob) -> Result<bool, TurtleExcavationError> {
+        let stop = match job.next_stop() {
+            Some(stop) => stop,
+            None => {
+                self.goto_excavation_home(&job).await?;
+                job.state = ExcavationState::Finished;
+                self.save_excavation(job).await?;
+                return Ok(false);
+            }
+        };
+
+        if let Some(reason) = self.excavation_break_reason(&job, stop.position).await? {
+            self.goto_excavation_home(&job).await?;
+            job.state = ExcavationState::Paused { reason };
+            self.save_excavation(job).await?;
+            return Ok(false);
+        }
+
+        self.goto(stop.position, Some(&Region::new(job.min, job.max))).await?;
+        if stop.dig_up {
+            self.destroy_block(JsonTurtleDirection::Up).await?;
+        }
+        if stop.dig_down {
+            self.destroy_block(JsonTurtleDirection::Down).await?;
+        }
+
+        job.done += 1;
+        self.save_excavation(job).await?;
+        Ok(true)
+    }
 
-        for i in 1..=16 {
-            let result = self.command(&format!("local item = turtle.getItemDetail({}) if (item ~= nil) then return textutils.serialiseJSON(item) else return nil end", i)).await?;
-            if result == "nil" {
-                continue;
+    /// # Returns
+    /// Why the turtle has to go home before it digs the next stop
+    async fn excavation_break_reason(&mut self, job: &ExcavationJob, next: BlockPosition) -> Result<Option<String>, TurtleExcavationError> {
+        if let TurtleFuel::Limited { level, .. } = self.database.turtle_data.fuel {
+            let needed = distance(self.position(), next) + distance(next, job.home);
+            if level < i64::from(needed) + EXCAVATION_FUEL_RESERVE {
+                return Ok(Some("Fuel is low".to_string()));
             }
-            let json: Value = serde_json::from_str(&result).or(Err(TurtleGetInventoryError::TurtleResponseNotJson))?;
-            tracing::debug!("INV: VAL = {json:?}");
+        }
+
+        //Blocks dug with a full inventory are dropped in the world
+        let inventory = self.get_inventory().await?;
+        if inventory.iter().filter(|slot| slot.is_none()).count() < EXCAVATION_FREE_SLOTS {
+            return Ok(Some("Inventory is full".to_string()));
+        }
+
+        Ok(None)
+    }
+
+    /// Only blocks of the job are dug on the way, home has to be reachable through air
+    async fn goto_excavation_home(&mut self, job: &ExcavationJob) -> Result<(), TurtleGotoError> {
+        self.goto(job.home, Some(&Region::new(job.min, job.max))).await
+    }
+
+    pub async fn place_block(&mut self, side: JsonTurtleDirection, slot: u8) -> Result<PlaceBlockResponse, TurtlePlaceBlockError> {
+        if !(1..=16).contains(&slot) {
+            return Err(TurtlePlaceBlockError::InvalidSlot(slot));
+        }
+
+        let response = self.send(TurtleCommand::Place { side: side.clone(), slot }).await?;
+        //For example "No items to place"
+        response.success().map_err(TurtlePlaceBlockError::CannotPlace)?;
+        //Placed items (like buckets) do not always leave a block
+        let block = match response.get(1) {
+            Value::Object(_) => Some(response.parse::<TurtleBlock>(1).map_err(TurtleWorldScanError::from)?),
+            _ => None,
+        };
+
+        let (x_diff, y_diff, z_diff) = side.to_turtle_side_diff(&self.database.turtle_data.rotation);
+        let (x, y, z) = (self.database.turtle_data.x + x_diff, self.database.turtle_data.y + y_diff, self.database.turtle_data.z + z_diff);
+
+        let mut world = self.database.world.lock().await;
+        let change = record_inspected_block(world.world_mut(), block, x, y, z)?;
+        if let Some(change) = &change {
+            self.emit_world_change(world.name(), change);
+        }
+
+        Ok(PlaceBlockResponse { change })
+    }
+
+    /// # Returns
+    /// All 16 slots, index 0 is slot 1. Empty slots are None
+    pub async fn get_inventory(&mut self) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleGetInventoryError> {
+        let result = self.command(GET_INVENTORY_PAYLOAD).await?;
+        parse_inventory(&result)
+    }
+
+    /// Runs an inventory payload that returns `true` on success or `false, reason`
+    async fn inventory_action(&mut self, payload: &str) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        //The inventory is read in the same round trip
+        let responses = self.batch(vec![
+            TurtleCommand::RawLua(payload.to_string()),
+            TurtleCommand::RawLua(GET_INVENTORY_PAYLOAD.to_string()),
+        ]).await?;
+        let (response, inventory) = match &responses[..] {
+            [response, inventory] => (response, inventory),
+            _ => return Err(TurtleRequestError::InvalidResponse.into())
+        };
+        response.success().map_err(TurtleInventoryActionError::ActionFailed)?;
+
+        let inventory = parse_inventory(inventory)?;
+        self.emit(TurtleEvent::InventoryUpdate {
+            uuid: self.database.turtle_data.uuid,
+            inventory: inventory.clone(),
+        });
+
+        Ok(inventory)
+    }
+
+    pub async fn select_slot(&mut self, slot: u8) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        let slot = validate_slot(slot)?;
+        self.inventory_action(&format!("return turtle.select({slot})")).await
+    }
+
+    /// Moves items between slots. The selected slot is restored afterwards
+    pub async fn transfer_items(&mut self, request: TransferItemsRequest) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        let (from, to) = (validate_slot(request.from)?, validate_slot(request.to)?);
+        let count = request.count.map(|count| format!(", {count}")).unwrap_or_default();
+        self.inventory_action(&format!("local prev = turtle.getSelectedSlot() turtle.select({from}) local ok = turtle.transferTo({to}{count}) turtle.select(prev) if ok then return true end return false, \"Cannot transfer items\"")).await
+    }
+
+    /// Drops items from the selected slot
+    pub async fn drop_items(&mut self, request: ItemsSideRequest) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        let suffix = vertical_suffix(&request.side)?;
+        let count = lua_count(request.count);
+        self.inventory_action(&format!("return turtle.drop{suffix}({count})")).await
+    }
+
+    /// Sucks items from the world or a chest into the inventory
+    pub async fn suck_items(&mut self, request: ItemsSideRequest) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        let suffix = vertical_suffix(&request.side)?;
+        let count = lua_count(request.count);
+        self.inventory_action(&format!("return turtle.suck{suffix}({count})")).await
+    }
 
-            res.push(json["name"].as_str().ok_or(TurtleGetInventoryError::TurtleResponseNotJson)?.split_once(":").ok_or(TurtleGetInventoryError::InvalidName)?.1.to_string());
+    /// Equips the item in the selected slot. Only Left and Right are valid sides
+    pub async fn equip(&mut self, side: JsonTurtleDirection) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        let function = match side {
+            JsonTurtleDirection::Left => "equipLeft",
+            JsonTurtleDirection::Right => "equipRight",
+            side => return Err(TurtleInventoryActionError::InvalidSide(side))
         };
 
-        Ok(res)
+        self.inventory_action(&format!("return turtle.{function}()")).await
+    }
+
+    /// Refuels using the items in the selected slot
+    pub async fn refuel(&mut self, request: RefuelRequest) -> Result<Vec<Option<TurtleInventoryItem>>, TurtleInventoryActionError> {
+        let count = lua_count(request.count);
+        let inventory = self.inventory_action(&format!("return turtle.refuel({count})")).await?;
+        self.refresh_fuel().await?;
+        self.publish_update();
+
+        Ok(inventory)
     }
 }
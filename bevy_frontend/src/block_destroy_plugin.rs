@@ -1,25 +1,18 @@
 use bevy::prelude::*;
 use bevy_mod_raycast::{DefaultRaycastingPlugin, RaycastMethod, RaycastSource, RaycastSystem};
 use crossbeam_channel::{Sender, Receiver, bounded};
-use shared::{DestroyBlockResponse, JsonTurtleDirection};
+use shared::{DestroyBlockResponse, JsonTurtle, JsonTurtleDirection, PlaceBlockRequest, PlaceBlockResponse, WorldChange};
 use std::error::Error;
 use uuid::Uuid;
 
-#[cfg(target_arch = "wasm32")]
-use {
-    wasm_bindgen::{JsCast, JsValue},
-    wasm_bindgen_futures::{spawn_local, JsFuture},
-    web_sys::{Request, RequestInit, Response},
-};
-
-use crate::{spawn_async, BlockRaycastSet, MainTurtle, WorldChangeEvent};
+use crate::{spawn_async, BlockRaycastSet, MainTurtle, SelectedInventorySlot, WorldChangeEvent};
 
 pub struct BlockDestroyPlugin;
 
 #[derive(Resource)]
 struct BlockDestroyGate {
-    destroy_sender: Sender<DestroyBlockResponse>,
-    destroy_reciver: Receiver<DestroyBlockResponse>,
+    change_sender: Sender<Option<WorldChange>>,
+    change_reciver: Receiver<Option<WorldChange>>,
 }
 
 fn update_raycast_with_cursor(
@@ -37,116 +30,103 @@ fn update_raycast_with_cursor(
     }
 }
 
+/// # Returns
+/// Global xyz of the block under the cursor and the normal of the face that was hit
+pub(crate) fn raycast_block(query_ray: &Query<&RaycastSource<BlockRaycastSet>>) -> Option<(IVec3, IVec3)> {
+    for source in query_ray {
+        if let Some((_, intersection)) = source.get_nearest_intersection() {
+            let normal = intersection.normal();
+            //Move a bit into the block so we do not land on the face between two blocks
+            //Chunk meshes are rendered 0.5 higher, see load_chunk_from_queue
+            let inside = intersection.position() - normal * 0.5 - Vec3::new(0.0, 0.5, 0.0);
+
+            return Some((inside.floor().as_ivec3(), normal.round().as_ivec3()));
+        }
+    }
+
+    None
+}
+
+/// # Returns
+/// The side of the turtle that is touching the given block
+fn turtle_side_of_block(turtle: &JsonTurtle, block: IVec3) -> Option<JsonTurtleDirection> {
+    [
+        JsonTurtleDirection::Forward,
+        JsonTurtleDirection::Backward,
+        JsonTurtleDirection::Left,
+        JsonTurtleDirection::Right,
+        JsonTurtleDirection::Up,
+        JsonTurtleDirection::Down,
+    ]
+    .into_iter()
+    .find(|side| {
+        let (side_x, side_y, side_z) = side.to_turtle_side_diff(&turtle.rotation);
+        IVec3::new(turtle.x + side_x, turtle.y + side_y, turtle.z + side_z) == block
+    })
+}
+
 fn detect_block_destroy_from_mouse(
-    keyboard: Res<Input<MouseButton>>,
-    query_ray: Query<&mut RaycastSource<BlockRaycastSet>>,
-    transform_query: Query<&Transform>,
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    query_ray: Query<&RaycastSource<BlockRaycastSet>>,
     main_turtle: Res<MainTurtle>,
+    selected_slot: Res<SelectedInventorySlot>,
     destroy_block_gate: Res<BlockDestroyGate>,
 ) {
-    if keyboard.just_pressed(MouseButton::Middle) {
-        log::warn!("Click!");
-        for entity in &query_ray {
-            if let Some((entity, _)) = entity.get_nearest_intersection() {
-                let transform = transform_query
-                    .get_component::<Transform>(entity)
-                    .expect("Entity does not contain transform!");
-                let transform = transform.translation;
-                let (x, y, z) = (transform.x - 0.5, transform.y - 1.0, transform.z - 0.5);
-                let guard = main_turtle
-                    .read()
-                    .expect("Cannot lock main turtle, should never happen!");
-                let main_turtle_ref = match &*guard {
-                    Some(val) => val,
-                    None => return,
-                };
-
-                let uuid = main_turtle_ref.uuid;
-
-                let (forward_x, forward_y, forward_z) =
-                    JsonTurtleDirection::Forward.to_turtle_move_diff(&main_turtle_ref.rotation);
-                let (forward_x, forward_y, forward_z) = (
-                    forward_x as f32 + main_turtle_ref.x as f32,
-                    main_turtle_ref.y as f32 + forward_y as f32,
-                    forward_z as f32 + main_turtle_ref.z as f32,
-                );
-                let (back_x, back_y, back_z) =
-                    JsonTurtleDirection::Backward.to_turtle_move_diff(&main_turtle_ref.rotation);
-                let (back_x, back_y, back_z) = (
-                    back_x as f32 + main_turtle_ref.x as f32,
-                    main_turtle_ref.y as f32 + back_y as f32,
-                    back_z as f32 + main_turtle_ref.z as f32,
-                );
-
-                let direction = if forward_x == x && forward_z == z && forward_y == y {
-                    JsonTurtleDirection::Forward
-                } else if back_z == z && back_y == y && back_x == x {
-                    JsonTurtleDirection::Backward
-                } else if x == main_turtle_ref.x as f32
-                    && y == main_turtle_ref.y as f32 + 1.0
-                    && z == main_turtle_ref.z as f32
-                {
-                    //TODO: ??;
-                    return;
-                } else {
-                    return;
-                };
-
-                drop(guard);
-
-                let mut tx = destroy_block_gate.destroy_sender.clone();
-
-                spawn_async(async move {
-                    let string_direction = direction.to_string();
-
-                    #[cfg(target_arch = "wasm32")]
-                    {
-                        let window = web_sys::window().expect("no global `window` exists");
-                        let document = window.document().expect("should have a document on window");
-
-                        let mut url = document
-                            .base_uri()
-                            .expect("Base uri get fail")
-                            .expect("No base uri");
-                        url.push_str("turtle/");
-                        url.push_str(&uuid.to_string());
-                        url.push_str("/destroy/");
-
-                        let mut opts = RequestInit::new();
-                        opts.method("PUT");
-                        opts.body(Some(&JsValue::from_str(&string_direction)));
-
-                        let request = Request::new_with_str_and_init(&url, &opts)
-                            .expect("Cannot create new request");
-                        let resp_value = JsFuture::from(window.fetch_with_request(&request))
-                            .await
-                            .expect("Cannot fetch value");
-
-                        assert!(resp_value.is_instance_of::<Response>());
-                        let resp: Response =
-                            resp_value.dyn_into().expect("Cannot cast into response");
-
-                        if resp.status() != 200 {
-                            log::error!("Something went bad! :<");
-                            return;
-                        }
-
-                        let json = JsFuture::from(resp.json().expect("Cannot get json"))
-                            .await
-                            .expect("Cannot get future from JS");
-                        let result: DestroyBlockResponse =
-                            serde_wasm_bindgen::from_value(json).expect("Json serde error");
-                        tx.try_send(result)
-                            .expect("Cannot send block destroy result to bevy");
-                    }
-                    #[cfg(not(target_arch = "wasm32"))]
-                    {
-                        todo!()
-                    }
-                });
-            }
-        }
+    if !mouse.just_pressed(MouseButton::Middle) {
+        return;
     }
+
+    //Ctrl + middle click places the selected block on the clicked face, shift would also move the turtle down
+    let place = keys.pressed(KeyCode::LControl);
+
+    let (block, normal) = match raycast_block(&query_ray) {
+        Some(val) => val,
+        None => return,
+    };
+    let target = if place { block + normal } else { block };
+
+    let guard = main_turtle
+        .read()
+        .expect("Cannot lock main turtle, should never happen!");
+    let main_turtle_ref = match &*guard {
+        Some(val) => val,
+        None => return,
+    };
+
+    let uuid = main_turtle_ref.uuid;
+    let direction = match turtle_side_of_block(main_turtle_ref, target) {
+        Some(val) => val,
+        None => return,
+    };
+
+    drop(guard);
+
+    let tx = destroy_block_gate.change_sender.clone();
+    let slot = selected_slot.0;
+
+    spawn_async(async move {
+        let change = if place {
+            let request = PlaceBlockRequest {
+                side: direction,
+                slot,
+            };
+            send_block_place_request(&request, &uuid)
+                .await
+                .map(|response| response.change)
+        } else {
+            send_block_destroy_request(&direction, &uuid)
+                .await
+                .map(|response| response.change)
+        };
+
+        match change {
+            Ok(change) => tx
+                .try_send(change)
+                .expect("Cannot send block change result to bevy"),
+            Err(err) => log::error!("Cannot send block request: {err}"),
+        }
+    });
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -156,7 +136,9 @@ async fn send_block_destroy_request(
 ) -> Result<DestroyBlockResponse, Box<dyn Error>> {
     use gloo_net::http::Request;
 
-    let response = Request::put(&format!("/turtle/{uuid}/destroy/"))
+    use crate::with_api_token;
+
+    let response = with_api_token(Request::put(&format!("/turtle/{uuid}/destroy/")))
         .body(direction.to_string())
         .send()
         .await?
@@ -166,12 +148,73 @@ async fn send_block_destroy_request(
     Ok(response)
 }
 
-fn detect_block_destroy_response(
+#[cfg(not(target_arch = "wasm32"))]
+async fn send_block_destroy_request(
+    direction: &JsonTurtleDirection,
+    uuid: &Uuid,
+) -> Result<DestroyBlockResponse, Box<dyn Error + Send + Sync>> {
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
+
+    let path = format!("{}/turtle/{uuid}/destroy/", HTTP_BACKEND_URL);
+    let response = with_api_token(REQWEST_CLIENT.put(path))
+        .body(direction.to_string())
+        .send()
+        .await?
+        .json::<DestroyBlockResponse>()
+        .await?;
+
+    Ok(response)
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn send_block_place_request(
+    request: &PlaceBlockRequest,
+    uuid: &Uuid,
+) -> Result<PlaceBlockResponse, Box<dyn Error>> {
+    use gloo_net::http::Request;
+
+    use crate::with_api_token;
+
+    let response = with_api_token(Request::put(&format!("/turtle/{uuid}/place/")))
+        .json(request)?
+        .send()
+        .await?;
+
+    //Backend explains why nothing was placed (for example the slot is empty)
+    if !response.ok() {
+        return Err(response.text().await?.into());
+    }
+
+    Ok(response.json::<PlaceBlockResponse>().await?)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn send_block_place_request(
+    request: &PlaceBlockRequest,
+    uuid: &Uuid,
+) -> Result<PlaceBlockResponse, Box<dyn Error + Send + Sync>> {
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
+
+    let path = format!("{}/turtle/{uuid}/place/", HTTP_BACKEND_URL);
+    let response = with_api_token(REQWEST_CLIENT.put(path))
+        .json(request)
+        .send()
+        .await?;
+
+    //Backend explains why nothing was placed (for example the slot is empty)
+    if !response.status().is_success() {
+        return Err(response.text().await?.into());
+    }
+
+    Ok(response.json::<PlaceBlockResponse>().await?)
+}
+
+fn detect_block_change_response(
     mut world_change_writer: EventWriter<WorldChangeEvent>,
-    mut gate: ResMut<BlockDestroyGate>,
+    gate: Res<BlockDestroyGate>,
 ) {
-    if let Ok(response) = gate.destroy_reciver.recv() {
-        if let Some(change) = response.change {
+    while let Ok(change) = gate.change_reciver.try_recv() {
+        if let Some(change) = change {
             world_change_writer.send(WorldChangeEvent(change))
         }
     }
@@ -179,11 +222,11 @@ fn detect_block_destroy_response(
 
 impl Plugin for BlockDestroyPlugin {
     fn build(&self, app: &mut App) {
-        let (tx, rx) = bounded::<DestroyBlockResponse>(8);
+        let (tx, rx) = bounded::<Option<WorldChange>>(8);
         app.add_plugin(DefaultRaycastingPlugin::<BlockRaycastSet>::default())
             .insert_resource(BlockDestroyGate {
-                destroy_sender: tx,
-                destroy_reciver: rx,
+                change_sender: tx,
+                change_reciver: rx,
             })
             .add_system(
                 update_raycast_with_cursor
@@ -191,6 +234,6 @@ impl Plugin for BlockDestroyPlugin {
                     .before(RaycastSystem::BuildRays::<BlockRaycastSet>),
             )
             .add_system(detect_block_destroy_from_mouse.after(update_raycast_with_cursor))
-            .add_system(detect_block_destroy_response);
+            .add_system(detect_block_change_response);
     }
 }
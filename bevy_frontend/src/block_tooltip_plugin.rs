@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Id, RichText},
+    EguiContexts,
+};
+use bevy_mod_raycast::RaycastSource;
+use shared::world_structure::split_palette_entry;
+
+use crate::{block_destroy_plugin::raycast_block, world_plugin::GlobalWorld, BlockRaycastSet};
+
+pub struct BlockTooltipPlugin;
+
+/// Name, state and tags of the block under the cursor
+fn show_block_tooltip(
+    mut contexts: EguiContexts,
+    query_ray: Query<&RaycastSource<BlockRaycastSet>>,
+    global_world: Res<GlobalWorld>,
+) {
+    let world = match &**global_world {
+        Some(world) => world,
+        None => return,
+    };
+
+    let ctx = contexts.ctx_mut();
+    //Windows are drawn over the world
+    if ctx.is_pointer_over_area() {
+        return;
+    }
+
+    let (block, _) = match raycast_block(&query_ray) {
+        Some(val) => val,
+        None => return,
+    };
+    let voxel = match world.get_voxel_by_global_xyz(block.x, block.y, block.z) {
+        Some(voxel) if voxel.is_solid() => voxel,
+        _ => return,
+    };
+    let entry = match world.pallete.get_pallete_from_id(voxel.id) {
+        Some(val) => val,
+        None => return,
+    };
+    let tags = world.pallete.get_tags_from_id(voxel.id).unwrap_or_default();
+    let (name, state) = split_palette_entry(&entry);
+
+    egui::show_tooltip_at_pointer(ctx, Id::new("block_tooltip"), |ui| {
+        ui.label(RichText::new(name).strong());
+        ui.label(format!("{} {} {}", block.x, block.y, block.z));
+
+        for property in state.into_iter().flat_map(|state| state.split(',')) {
+            ui.label(property.replacen('=', ": ", 1));
+        }
+
+        if !tags.is_empty() {
+            ui.separator();
+            for tag in tags {
+                ui.label(RichText::new(&**tag).weak());
+            }
+        }
+    });
+}
+
+impl Plugin for BlockTooltipPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(show_block_tooltip);
+    }
+}
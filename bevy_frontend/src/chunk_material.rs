@@ -7,7 +7,7 @@ use bevy::{
         render_resource::{AsBindGroup, ShaderType, VertexFormat},
     },
 };
-use shared::world_structure::TurtleWorld;
+use shared::world_structure::{split_palette_entry, TurtleWorld};
 
 use crate::world_plugin::GlobalWorld;
 
@@ -116,7 +116,9 @@ fn update_chunk_material_singleton(
                 .take_while(|(i, _)| *i < 256)
                 .map(|(i, item_name)| {
                     log::warn!("{item_name}");
-                    let hash = seahash::hash(item_name.as_bytes());
+                    //Every state of a block has the same color
+                    let (block_name, _) = split_palette_entry(item_name);
+                    let hash = seahash::hash(block_name.as_bytes());
                     let hash: [u8; 8] = hash.to_le_bytes();
 
                     (i, Color::rgb_u8(hash[0], hash[4], hash[7]))
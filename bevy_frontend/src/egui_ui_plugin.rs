@@ -13,9 +13,9 @@ use bevy_egui::{
 };
 use crossbeam_channel::{Sender, Receiver, bounded};
 use egui_extras::RetainedImage;
-use shared::JsonTurtle;
+use shared::{JsonTurtle, TurtleEvent};
 
-use crate::{spawn_async, MainTurtle, SelectTurtleEvent};
+use crate::{spawn_async, MainTurtle, RemoteTurtleEvent, SelectTurtleEvent};
 
 type DynError = Box<dyn Error + Sync + Send>;
 
@@ -54,6 +54,7 @@ impl Plugin for UiPlugin {
                 fetching_rx: rx,
             })
             .add_system(recive_turtle_list.after(draw_egui_ui))
+            .add_system(apply_remote_turtle_events.after(recive_turtle_list))
             .add_startup_system(setup_font)
             .add_system(draw_egui_ui);
     }
@@ -117,7 +118,7 @@ fn draw_egui_ui(
                     };
 
                     //User clicked this button
-                    if turtle_button(ui, i, is_main_turtle) {
+                    if turtle_button(ui, i, is_main_turtle, turtle.online) {
                         if is_main_turtle {
                             return;
                         }
@@ -161,21 +162,26 @@ fn draw_egui_ui(
 
                     let response = image.response.interact(egui::Sense::click());
 
-                    if response.clicked() && !gate.fetching.load(Ordering::Relaxed) {
-                        gate.fetching.store(true, Ordering::Relaxed);
+                    if response.clicked() {
+                        start_fetching_turtles(&gate);
+                    }
 
-                        let mut tx = gate.fetching_tx.clone();
-                        spawn_async(async move {
-                            let res = fetch_turtles().await;
-                            tx.try_send(res).expect("Cannot send fetch result to bevy");
-                        })
+                    //We cannot block in the UI, the fuel will be shown next frame
+                    if let Ok(main_turtle) = main_turtle.try_read() {
+                        if let Some(main_turtle) = &*main_turtle {
+                            let text = RichText::new(format!("Fuel: {}", main_turtle.fuel.to_string()))
+                                .color(Color32::WHITE)
+                                .size(20.)
+                                .font(FontId::new(20.0, FontFamily::Name("ui-sans-serif".into())));
+                            ui.label(text);
+                        }
                     }
                 });
             });
         });
 }
 
-fn turtle_button(ui: &mut Ui, i: usize, is_main_turtle: bool) -> bool {
+fn turtle_button(ui: &mut Ui, i: usize, is_main_turtle: bool, online: bool) -> bool {
     //This took some anoying testing but it SHOULD work (hopefuly some update does not break this)
     let (start_x, start_y) = (ui.cursor().left_top().x, ui.cursor().left_top().y);
     let hovered = ui
@@ -191,6 +197,8 @@ fn turtle_button(ui: &mut Ui, i: usize, is_main_turtle: bool) -> bool {
 
     let bg_color = if hovered && !is_main_turtle {
         egui::Color32::from_rgb(34, 197, 94)
+    } else if !online {
+        egui::Color32::from_rgb(120, 113, 108)
     } else {
         egui::Color32::from_rgb(6, 182, 212)
     };
@@ -245,11 +253,56 @@ fn recive_turtle_list(mut gate: ResMut<UiGate>) {
     }
 }
 
+/// Fetches the turtle list unless it is already being fetched, see [recive_turtle_list]
+fn start_fetching_turtles(gate: &UiGate) {
+    if gate.fetching.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    let mut tx = gate.fetching_tx.clone();
+    spawn_async(async move {
+        let res = fetch_turtles().await;
+        tx.try_send(res).expect("Cannot send fetch result to bevy");
+    })
+}
+
+/// Keeps the turtle list up to date without pressing refresh
+fn apply_remote_turtle_events(
+    mut gate: ResMut<UiGate>,
+    mut remote_events: EventReader<RemoteTurtleEvent>,
+) {
+    for event in remote_events.iter() {
+        match &event.0 {
+            TurtleEvent::TurtleConnected(turtle) | TurtleEvent::TurtleUpdate(turtle) => {
+                match gate.all_turtles.iter().position(|old| old.uuid == turtle.uuid) {
+                    Some(i) => gate.all_turtles[i] = turtle.clone(),
+                    None => {
+                        if let TurtleEvent::TurtleConnected(_) = &event.0 {
+                            gate.all_turtles.push(turtle.clone());
+                        }
+                    }
+                }
+            }
+            TurtleEvent::TurtleDisconnected { uuid } => {
+                //Offline turtles stay in the list so they can be selected again once they reconnect
+                if let Some(turtle) = gate.all_turtles.iter_mut().find(|turtle| turtle.uuid == *uuid) {
+                    turtle.online = false;
+                }
+            }
+            //Connects and disconnects could have been missed
+            TurtleEvent::Resync => start_fetching_turtles(&gate),
+            _ => {}
+        }
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 async fn fetch_turtles() -> Result<Vec<JsonTurtle>, DynError> {
     use gloo_net::http::Request;
 
-    let resp = Request::get("/turtle/list/")
+    use crate::with_api_token;
+
+    let resp = with_api_token(Request::get("/turtle/list/"))
         .send()
         .await?
         .json::<Vec<JsonTurtle>>()
@@ -260,11 +313,15 @@ async fn fetch_turtles() -> Result<Vec<JsonTurtle>, DynError> {
 
 #[cfg(not(target_arch = "wasm32"))]
 async fn fetch_turtles() -> Result<Vec<JsonTurtle>, Box<dyn Error + Send + Sync>> {
-    use crate::HTTP_BACKEND_URL;
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
 
     let url = format!("{}/turtle/list/", HTTP_BACKEND_URL);
 
-    let resp = reqwest::get(&url).await?.json::<Vec<JsonTurtle>>().await?;
+    let resp = with_api_token(REQWEST_CLIENT.get(&url))
+        .send()
+        .await?
+        .json::<Vec<JsonTurtle>>()
+        .await?;
 
     Ok(resp)
 }
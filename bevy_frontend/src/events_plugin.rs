@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use shared::TurtleEvent;
+
+use crate::{api_token, MainTurtle, RemoteTurtleEvent, WorldChangeEvent};
+
+#[cfg(not(target_arch = "wasm32"))]
+static EVENTS_RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+#[cfg(target_arch = "wasm32")]
+static EVENTS_RECONNECT_DELAY_MS: i32 = 2000;
+
+/// Listens on the backend /events/ websocket so changes made by other turtles or tabs show up
+pub struct EventsPlugin;
+
+#[derive(Resource)]
+struct EventsGate {
+    event_rx: Receiver<TurtleEvent>,
+}
+
+impl Plugin for EventsPlugin {
+    fn build(&self, app: &mut App) {
+        let (tx, rx) = unbounded();
+        connect_events_socket(tx);
+
+        app.insert_resource(EventsGate { event_rx: rx })
+            .add_system(recive_events);
+    }
+}
+
+fn recive_events(
+    gate: Res<EventsGate>,
+    main_turtle: Res<MainTurtle>,
+    mut world_change_writer: EventWriter<WorldChangeEvent>,
+    mut remote_turtle_writer: EventWriter<RemoteTurtleEvent>,
+) {
+    while let Ok(event) = gate.event_rx.try_recv() {
+        match event {
+            TurtleEvent::WorldChange { world, change } => {
+                //Only the world of the main turtle is loaded
+                let same_world = main_turtle
+                    .read()
+                    .expect("Cannot lock main turtle, should never happen!")
+                    .as_ref()
+                    .is_some_and(|turtle| turtle.world == world);
+
+                if same_world {
+                    world_change_writer.send(WorldChangeEvent(change));
+                }
+            }
+            event => remote_turtle_writer.send(RemoteTurtleEvent(event)),
+        }
+    }
+}
+
+fn forward_event(text: &str, tx: &Sender<TurtleEvent>) {
+    match serde_json::from_str::<TurtleEvent>(text) {
+        Ok(event) => tx.send(event).expect("Cannot pass event into bevy"),
+        Err(err) => log::error!("Invalid event from backend: {err}"),
+    }
+}
+
+/// Websockets cannot send the Authorization header, the token goes in the query
+fn events_url(url: String) -> String {
+    match api_token() {
+        Some(token) => format!("{url}?token={}", encode_query_value(&token)),
+        None => url,
+    }
+}
+
+/// Percent encodes everything but the unreserved characters, the backend decodes the query
+fn encode_query_value(val: &str) -> String {
+    val.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn connect_events_socket(tx: Sender<TurtleEvent>) {
+    use futures_util::StreamExt;
+    use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+    use crate::{spawn_async, HTTP_BACKEND_URL};
+
+    let url = events_url(format!("{}/events/", HTTP_BACKEND_URL.replacen("http", "ws", 1)));
+    spawn_async(async move {
+        loop {
+            match connect_async(&url).await {
+                Ok((mut socket, _)) => {
+                    while let Some(Ok(msg)) = socket.next().await {
+                        if let Message::Text(text) = msg {
+                            forward_event(&text, &tx);
+                        }
+                    }
+                    log::warn!("Events socket closed, reconnecting");
+                }
+                Err(err) => log::error!("Cannot connect to the events socket: {err}"),
+            }
+
+            tokio::time::sleep(EVENTS_RECONNECT_DELAY).await;
+        }
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn connect_events_socket(tx: Sender<TurtleEvent>) {
+    use wasm_bindgen::{closure::Closure, JsCast};
+    use web_sys::{MessageEvent, WebSocket};
+
+    let window = web_sys::window().expect("Cannot get window");
+    let location = window.location();
+    let protocol = match location.protocol().as_deref() {
+        Ok("https:") => "wss:",
+        _ => "ws:",
+    };
+    let host = location.host().expect("Cannot get page host");
+
+    let socket = match WebSocket::new(&events_url(format!("{protocol}//{host}/events/"))) {
+        Ok(val) => val,
+        Err(err) => {
+            log::error!("Cannot connect to the events socket: {err:?}");
+            return;
+        }
+    };
+
+    let message_tx = tx.clone();
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        if let Some(text) = event.data().as_string() {
+            forward_event(&text, &message_tx);
+        }
+    });
+    socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    //The socket lives as long as the page does
+    onmessage.forget();
+
+    let onclose = Closure::once_into_js(move || {
+        log::warn!("Events socket closed, reconnecting");
+        let reconnect = Closure::once_into_js(move || connect_events_socket(tx));
+        let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            reconnect.unchecked_ref(),
+            EVENTS_RECONNECT_DELAY_MS,
+        );
+    });
+    socket.set_onclose(Some(onclose.unchecked_ref()));
+}
@@ -1,43 +1,64 @@
+use std::error::Error;
+
 use bevy::prelude::*;
-use bevy_egui::{EguiPlugin, EguiContexts, egui::{self, Align2, ScrollArea, TextStyle, FontFamily::Proportional, FontId, Visuals, Color32}};
+use bevy_egui::{EguiContexts, egui::{self, Align2, FontFamily::Proportional, FontId, Visuals, Color32}};
 use bevy_panorbit_camera::PanOrbitCamera;
-use gloo_net::http::Request;
-use shared::{TurtleInventoryItem, JsonTurtle};
-use wasm_bindgen_futures::spawn_local;
+use crossbeam_channel::{Sender, Receiver, bounded};
+use shared::{ItemsSideRequest, JsonTurtleDirection, RefuelRequest, TransferItemsRequest, TurtleEvent, TurtleInventoryItem};
+use uuid::Uuid;
+
+use crate::{spawn_async, MainTurtle, RemoteTurtleEvent, SelectTurtleEvent, SelectedInventorySlot};
 
-use crate::ui_plugin::MainTurtle;
+type DynError = Box<dyn Error + Sync + Send>;
+type TurtleInventory = Vec<Option<TurtleInventoryItem>>;
 
 pub struct InventoryPlugin;
 
+enum InventoryAction {
+    Select(u8),
+    Transfer(TransferItemsRequest),
+    Drop(ItemsSideRequest),
+    Refuel(RefuelRequest),
+}
+
+impl InventoryAction {
+    fn path(&self) -> &'static str {
+        match self {
+            InventoryAction::Select(_) => "select",
+            InventoryAction::Transfer(_) => "transfer",
+            InventoryAction::Drop(_) => "drop",
+            InventoryAction::Refuel(_) => "refuel",
+        }
+    }
+}
+
 #[derive(Resource)]
 struct TurtleInventoryResource {
-    list: Vec<TurtleInventoryItem>,
+    list: TurtleInventory,
     open: bool,
-    open_changed: bool
+    camera_locked: bool,
+    fetch_tx: Sender<Result<TurtleInventory, DynError>>,
+    fetch_rx: Receiver<Result<TurtleInventory, DynError>>,
 }
 
 impl Plugin for InventoryPlugin {
     fn build(&self, app: &mut App) {
+        let (tx, rx) = bounded(8);
+
         app
             .insert_resource(TurtleInventoryResource {
-                list: vec![
-                    TurtleInventoryItem {
-                        name: "gold_block".to_string(),
-                        count: 1,
-                        selected: false
-                    },
-                    TurtleInventoryItem {
-                        name: "diamond_block".to_string(),
-                        count: 10,
-                        selected: true
-                    }
-                ],
+                list: vec![None; 16],
                 open: false,
-                open_changed: false
+                camera_locked: false,
+                fetch_tx: tx,
+                fetch_rx: rx,
             })
             .add_startup_system(setup_text_styles)
             .add_system(open_ui_based_on_keyboard)
-            .add_system(ui_example_system);
+            .add_system(on_turtle_change)
+            .add_system(on_remote_inventory_update.before(recive_inventory))
+            .add_system(recive_inventory)
+            .add_system(inventory_window.after(open_ui_based_on_keyboard));
     }
 }
 
@@ -64,107 +85,276 @@ fn setup_text_styles(
 fn open_ui_based_on_keyboard(
     keys: Res<Input<KeyCode>>,
     mut inventory_res: ResMut<TurtleInventoryResource>,
-    mut camera_query: Query<&mut PanOrbitCamera>,
     main_turtle: Res<MainTurtle>,
 ) {
-    let open_changed = *&inventory_res.open_changed;
-    let open = &mut inventory_res.open;
-    if keys.just_pressed(KeyCode::E) {
-        *open = !*open;
-
-        if *open {
-            //we had just opend the gui
-            let main_turtle = main_turtle.read().expect("Cannot read main_turtle");
-            match main_turtle.as_ref() {
-                Some(val) => fetch_remote_inventory(val),
-                None => {},
-            }
+    if !keys.just_pressed(KeyCode::E) {
+        return;
+    }
+
+    inventory_res.open = !inventory_res.open;
+
+    if inventory_res.open {
+        //we had just opend the gui
+        let main_turtle = main_turtle.read().expect("Cannot read main_turtle");
+        if let Some(val) = main_turtle.as_ref() {
+            fetch_remote_inventory(val.uuid, inventory_res.fetch_tx.clone());
         }
     }
+}
 
-    if open_changed {
-        let mut camera = camera_query.single_mut();
+fn on_turtle_change(
+    mut ev_change: EventReader<SelectTurtleEvent>,
+    mut inventory_res: ResMut<TurtleInventoryResource>,
+) {
+    for ev in ev_change.iter() {
+        inventory_res.list = vec![None; 16];
 
-        if *open {
-            camera.orbit_sensitivity = 0.0;
-            camera.zoom_sensitivity = 0.0;
-        } else {
-            camera.orbit_sensitivity = 1.0;
-            camera.zoom_sensitivity = 1.0;
+        if let (Some(turtle), true) = (&ev.0, inventory_res.open) {
+            fetch_remote_inventory(turtle.uuid, inventory_res.fetch_tx.clone());
         }
+    }
+}
 
+fn on_remote_inventory_update(
+    mut remote_events: EventReader<RemoteTurtleEvent>,
+    inventory_res: Res<TurtleInventoryResource>,
+    main_turtle: Res<MainTurtle>,
+) {
+    for event in remote_events.iter() {
+        if let TurtleEvent::InventoryUpdate { uuid, inventory } = &event.0 {
+            let is_main_turtle = main_turtle
+                .read()
+                .expect("Cannot read main_turtle")
+                .as_ref()
+                .is_some_and(|turtle| turtle.uuid == *uuid);
+
+            if is_main_turtle {
+                inventory_res.fetch_tx.try_send(Ok(inventory.clone())).expect("Cannot send inventory to bevy");
+            }
+        }
     }
 }
 
 fn fetch_remote_inventory(
-    main_turtle: &JsonTurtle
+    uuid: Uuid,
+    tx: Sender<Result<TurtleInventory, DynError>>,
+) {
+    spawn_async(async move {
+        let res = send_get_inventory_request(&uuid).await;
+        tx.try_send(res).expect("Cannot send inventory to bevy");
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn send_get_inventory_request(uuid: &Uuid) -> Result<TurtleInventory, DynError> {
+    use gloo_net::http::Request;
+
+    use crate::with_api_token;
+
+    let resp = with_api_token(Request::get(&format!("/turtle/{uuid}/inventory/")))
+        .send()
+        .await?
+        .json::<TurtleInventory>()
+        .await?;
+
+    Ok(resp)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn send_get_inventory_request(uuid: &Uuid) -> Result<TurtleInventory, DynError> {
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
+
+    let path = format!("{}/turtle/{uuid}/inventory/", HTTP_BACKEND_URL);
+    let resp = with_api_token(REQWEST_CLIENT.get(path))
+        .send()
+        .await?
+        .json::<TurtleInventory>()
+        .await?;
+
+    Ok(resp)
+}
+
+fn send_inventory_action(
+    uuid: Uuid,
+    action: InventoryAction,
+    tx: Sender<Result<TurtleInventory, DynError>>,
+) {
+    spawn_async(async move {
+        let res = send_inventory_action_request(&uuid, &action).await;
+        tx.try_send(res).expect("Cannot send inventory to bevy");
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn send_inventory_action_request(uuid: &Uuid, action: &InventoryAction) -> Result<TurtleInventory, DynError> {
+    use gloo_net::http::Request;
+
+    use crate::with_api_token;
+
+    let request = with_api_token(Request::put(&format!("/turtle/{uuid}/inventory/{}/", action.path())));
+    let request = match action {
+        InventoryAction::Select(slot) => request.body(slot.to_string()),
+        InventoryAction::Transfer(body) => request.json(body)?,
+        InventoryAction::Drop(body) => request.json(body)?,
+        InventoryAction::Refuel(body) => request.json(body)?,
+    };
+
+    let resp = request
+        .send()
+        .await?
+        .json::<TurtleInventory>()
+        .await?;
+
+    Ok(resp)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn send_inventory_action_request(uuid: &Uuid, action: &InventoryAction) -> Result<TurtleInventory, DynError> {
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
+
+    let path = format!("{}/turtle/{uuid}/inventory/{}/", HTTP_BACKEND_URL, action.path());
+    let request = with_api_token(REQWEST_CLIENT.put(path));
+    let request = match action {
+        InventoryAction::Select(slot) => request.body(slot.to_string()),
+        InventoryAction::Transfer(body) => request.json(body),
+        InventoryAction::Drop(body) => request.json(body),
+        InventoryAction::Refuel(body) => request.json(body),
+    };
+
+    let resp = request
+        .send()
+        .await?
+        .json::<TurtleInventory>()
+        .await?;
+
+    Ok(resp)
+}
+
+fn recive_inventory(
+    mut inventory_res: ResMut<TurtleInventoryResource>,
+    mut selected_slot: ResMut<SelectedInventorySlot>,
 ) {
-    let path = format!("/turtle/{}/inventory/", main_turtle.uuid);
-
-    spawn_local(async move {
-        let resp = Request::get(&path)
-            .send()
-            .await;
-
-        match resp {
-            Ok(resp) => {
-                let json = match resp.json::<Vec<String>>().await {
-                    Ok(val) => val,
-                    Err(err) => {
-                        log::error!("Cannot parse inventory response as JSON! Err: {err}");
-                        return;
-                    },
-                };
-
-                log::warn!("New turtle inventory: {json:?}")
+    while let Ok(response) = inventory_res.fetch_rx.try_recv() {
+        match response {
+            Ok(inventory) => {
+                if let Some(slot) = inventory.iter().position(|item| item.as_ref().is_some_and(|item| item.selected)) {
+                    selected_slot.0 = (slot + 1) as u8;
+                }
+                inventory_res.list = inventory;
             },
             Err(err) => log::error!("Feching inventory went wrong {err}"),
         }
-
-    });
+    }
 }
 
-fn ui_example_system(
+fn inventory_window(
     mut contexts: EguiContexts,
     mut inventory_res: ResMut<TurtleInventoryResource>,
+    mut selected_slot: ResMut<SelectedInventorySlot>,
+    mut camera_query: Query<&mut PanOrbitCamera>,
+    main_turtle: Res<MainTurtle>,
     window: Query<&Window>,
 ) {
     let window = window.single();
-    let open = &mut inventory_res.open.clone();
+    let mut open = inventory_res.open;
+    let mut action: Option<InventoryAction> = None;
 
     egui::Window::new("Turtle Inventory")
         .pivot(Align2::CENTER_CENTER)
         .fixed_pos([window.width() / 2.0, window.height() / 2.0])
         .collapsible(false)
         .resizable(false)
-        .open(open)
+        .open(&mut open)
         .show(contexts.ctx_mut(), |ui| {
-            ScrollArea::vertical().auto_shrink([false; 2]).show_rows(
-                ui,
-                ui.text_style_height(&TextStyle::Body),
-                inventory_res.list.len(),
-                |ui, row_range| {
-                    row_range
-                        .into_iter()
-                        .for_each(|row| {
-                            let item = &inventory_res.list[row];
-                            let lablel = ui.selectable_label(item.selected, inventory_res.list[row].name.clone());
-
-                            if !lablel.clicked() {
-                                return;
-                            }
-
-                            inventory_res.list
-                                .iter_mut()
-                                .enumerate()
-                                .for_each(|(id, item)| {
-                                    item.selected = id == row;
-                                })
-                        });
+            let mut slot_rects = Vec::with_capacity(16);
+            let mut drag_released_from: Option<usize> = None;
+
+            egui::Grid::new("turtle_inventory_grid").show(ui, |ui| {
+                for (i, item) in inventory_res.list.iter().enumerate() {
+                    let text = match item {
+                        //Namespace is only shown on hover so the slot fits the name
+                        Some(item) => format!("{}\n{}", item.name.split_once(':').map_or(item.name.as_str(), |(_, name)| name), item.count),
+                        None => String::new(),
+                    };
+
+                    let mut response = ui
+                        .add_sized(
+                            [96., 56.],
+                            egui::SelectableLabel::new(selected_slot.0 as usize == i + 1, text),
+                        )
+                        .interact(egui::Sense::click_and_drag());
+                    if let Some(item) = item {
+                        response = response.on_hover_text(&item.name);
+                    }
+
+                    if response.clicked() {
+                        action = Some(InventoryAction::Select((i + 1) as u8));
+                    }
+                    if response.drag_released() {
+                        drag_released_from = Some(i);
+                    }
+                    slot_rects.push(response.rect);
+
+                    if (i + 1) % 4 == 0 {
+                        ui.end_row();
+                    }
+                }
+            });
+
+            //Dragging one slot onto another moves the items
+            if let Some(from) = drag_released_from {
+                let pointer = ui.input(|input| input.pointer.hover_pos());
+                let to = pointer.and_then(|pointer| slot_rects.iter().position(|rect| rect.contains(pointer)));
+
+                if let Some(to) = to.filter(|to| *to != from) {
+                    action = Some(InventoryAction::Transfer(TransferItemsRequest {
+                        from: (from + 1) as u8,
+                        to: (to + 1) as u8,
+                        count: None,
+                    }));
+                }
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Drop").clicked() {
+                    action = Some(InventoryAction::Drop(ItemsSideRequest {
+                        side: JsonTurtleDirection::Forward,
+                        count: None,
+                    }));
+                }
+                if ui.button("Refuel").clicked() {
+                    action = Some(InventoryAction::Refuel(RefuelRequest { count: None }));
                 }
-            );
+            });
         });
- 
-    inventory_res.open = *open;
-    inventory_res.open_changed = inventory_res.open != *open; 
+
+    if let Some(action) = action {
+        if let InventoryAction::Select(slot) = &action {
+            selected_slot.0 = *slot;
+            inventory_res.list
+                .iter_mut()
+                .enumerate()
+                .for_each(|(id, item)| {
+                    if let Some(item) = item {
+                        item.selected = id + 1 == *slot as usize;
+                    }
+                });
+        }
+
+        let main_turtle = main_turtle.read().expect("Cannot read main_turtle");
+        if let Some(turtle) = main_turtle.as_ref() {
+            send_inventory_action(turtle.uuid, action, inventory_res.fetch_tx.clone());
+        }
+    }
+
+    //Do not orbit the camera while clicking around the inventory
+    if open != inventory_res.camera_locked {
+        let mut camera = camera_query.single_mut();
+        let sensitivity = if open { 0.0 } else { 1.0 };
+        camera.orbit_sensitivity = sensitivity;
+        camera.zoom_sensitivity = sensitivity;
+        inventory_res.camera_locked = open;
+    }
+
+    inventory_res.open = open;
 }
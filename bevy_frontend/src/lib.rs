@@ -1,8 +1,12 @@
 mod move_plugin;
 
 mod block_destroy_plugin;
+mod block_tooltip_plugin;
 mod chunk_material;
 mod egui_ui_plugin;
+mod events_plugin;
+mod inventory_plugin;
+mod scan_plugin;
 mod world_plugin;
 
 #[cfg(target_arch = "wasm32")]
@@ -18,9 +22,13 @@ use bevy_mod_raycast::RaycastSource;
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
 use chunk_material::ChunkMaterialPlugin;
 use egui_ui_plugin::UiPlugin;
-//use block_destroy_plugin::BlockDestroyPlugin;
+use events_plugin::EventsPlugin;
+use inventory_plugin::InventoryPlugin;
+use block_destroy_plugin::BlockDestroyPlugin;
+use block_tooltip_plugin::BlockTooltipPlugin;
 use move_plugin::MovePlugin;
-use shared::{JsonTurtle, WorldChange};
+use scan_plugin::ScanPlugin;
+use shared::{JsonTurtle, TurtleEvent, WorldChange};
 use std::future::Future;
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::runtime::{Builder, Runtime};
@@ -50,6 +58,13 @@ pub struct SelectTurtleEvent(Option<JsonTurtle>);
 
 pub struct WorldChangeEvent(WorldChange);
 
+/// Turtle events from the backend events socket, world changes are sent as [WorldChangeEvent]
+pub struct RemoteTurtleEvent(TurtleEvent);
+
+/// Inventory slot (1-16) used when placing blocks
+#[derive(Resource)]
+pub struct SelectedInventorySlot(pub u8);
+
 #[cfg(target_arch = "wasm32")]
 pub fn spawn_async<F>(future: F)
 where
@@ -73,6 +88,43 @@ static TOKIO_RUNTIME: once_cell::sync::Lazy<Runtime> = once_cell::sync::Lazy::ne
 static REQWEST_CLIENT: once_cell::sync::Lazy<reqwest::Client> =
     once_cell::sync::Lazy::new(|| reqwest::Client::new());
 
+/// Backend api token, native reads WEB_TURTLE_TOKEN and the web page reads `?token=` of its url
+#[cfg(not(target_arch = "wasm32"))]
+static API_TOKEN: once_cell::sync::Lazy<Option<String>> = once_cell::sync::Lazy::new(|| {
+    std::env::var("WEB_TURTLE_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+});
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn api_token() -> Option<String> {
+    API_TOKEN.clone()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn api_token() -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get("token")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn with_api_token(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match api_token() {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn with_api_token(request: gloo_net::http::Request) -> gloo_net::http::Request {
+    match api_token() {
+        Some(token) => request.header("Authorization", &format!("Bearer {token}")),
+        None => request,
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn spawn_async<F>(future: F)
 where
@@ -136,15 +188,20 @@ async fn async_main() {
             ..default()
         }))
         .insert_resource(Msaa::Sample4)
+        .insert_resource(SelectedInventorySlot(1))
         .add_event::<SelectTurtleEvent>()
         .add_event::<WorldChangeEvent>()
+        .add_event::<RemoteTurtleEvent>()
         .add_plugin(PanOrbitCameraPlugin)
         .add_plugin(MovePlugin)
         .add_plugin(WorldPlugin)
         .add_plugin(PlatformIndependentPlugins)
         .add_plugin(UiPlugin)
-        //.add_plugin(BlockDestroyPlugin)
-        //.add_plugin(InventoryPlugin)
+        .add_plugin(BlockDestroyPlugin)
+        .add_plugin(BlockTooltipPlugin)
+        .add_plugin(ScanPlugin)
+        .add_plugin(InventoryPlugin)
+        .add_plugin(EventsPlugin)
         .add_plugin(ChunkMaterialPlugin)
         .add_plugin(EguiPlugin)
         .add_plugin(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
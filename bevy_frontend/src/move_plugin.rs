@@ -3,11 +3,12 @@ use std::{error::Error, time::Duration};
 use bevy::prelude::*;
 use bevy_panorbit_camera::PanOrbitCamera;
 use crossbeam_channel::{Receiver, Sender, bounded};
-use shared::{JsonTurtle, JsonTurtleDirection, TurtleMoveResponse};
+use shared::{JsonTurtle, JsonTurtleDirection, TurtleEvent, TurtleMoveResponse};
 use uuid::Uuid;
 
 use crate::{
-    spawn_async, MainCamera, MainTurtle, MainTurtleObject, SelectTurtleEvent, WorldChangeEvent,
+    spawn_async, MainCamera, MainTurtle, MainTurtleObject, RemoteTurtleEvent, SelectTurtleEvent,
+    WorldChangeEvent,
 };
 
 pub struct MovePlugin;
@@ -34,6 +35,7 @@ impl Plugin for MovePlugin {
         .add_system(control_timer)
         .add_system(keybord_input)
         .add_system(recive_notification)
+        .add_system(follow_remote_turtle_updates)
         .add_system(on_turtle_change);
     }
 }
@@ -62,6 +64,10 @@ fn keybord_input(
         JsonTurtleDirection::Left
     } else if keys.pressed(KeyCode::D) {
         JsonTurtleDirection::Right
+    } else if keys.pressed(KeyCode::Space) {
+        JsonTurtleDirection::Up
+    } else if keys.pressed(KeyCode::LShift) {
+        JsonTurtleDirection::Down
     } else {
         return;
     };
@@ -87,10 +93,18 @@ fn keybord_input(
     spawn_async(async move {
         let resp = send_move_request(&direction, &uuid).await;
 
-        match resp {
+        let (x, y, z, rotation, fuel) = match resp {
             Ok(result) => {
+                let state = (
+                    result.x,
+                    result.y,
+                    result.z,
+                    result.rotation.clone(),
+                    result.fuel.clone(),
+                );
                 tx.try_send(Some(result))
                     .expect("Cannot notify bevy move system (Ok)");
+                state
             }
             Err(err) => {
                 log::error!("Cannot send move request: {err}");
@@ -98,25 +112,19 @@ fn keybord_input(
                     .expect("Cannot notify bevy move system (Err)");
                 return;
             }
-        }
+        };
 
         main_turtle
             .write()
             .expect("Cannot lock main turtle, should never happen!")
             .as_mut()
             .and_then(|main_turtle| {
-                match direction {
-                    JsonTurtleDirection::Right | JsonTurtleDirection::Left => {
-                        main_turtle.rotation.rotate_self(&direction);
-                    }
-                    JsonTurtleDirection::Backward | JsonTurtleDirection::Forward => {
-                        let (x_change, y_change, z_change) =
-                            direction.to_turtle_move_diff(&main_turtle.rotation);
-                        main_turtle.x += x_change;
-                        main_turtle.y += y_change;
-                        main_turtle.z += z_change;
-                    }
-                };
+                //Backend position is used, the events socket could have updated the turtle already
+                main_turtle.x = x;
+                main_turtle.y = y;
+                main_turtle.z = z;
+                main_turtle.rotation = rotation;
+                main_turtle.fuel = fuel;
                 None::<()>
             });
     })
@@ -129,14 +137,19 @@ async fn send_move_request(
 ) -> Result<TurtleMoveResponse, Box<dyn Error>> {
     use gloo_net::http::Request;
 
-    let response = Request::put(&format!("/turtle/{uuid}/move/"))
+    use crate::with_api_token;
+
+    let response = with_api_token(Request::put(&format!("/turtle/{uuid}/move/")))
         .body(direction.to_string())
         .send()
-        .await?
-        .json::<TurtleMoveResponse>()
         .await?;
 
-    Ok(response)
+    //Backend explains why the turtle cannot move (for example it is out of fuel)
+    if !response.ok() {
+        return Err(response.text().await?.into());
+    }
+
+    Ok(response.json::<TurtleMoveResponse>().await?)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -144,18 +157,20 @@ async fn send_move_request(
     direction: &JsonTurtleDirection,
     uuid: &Uuid,
 ) -> Result<TurtleMoveResponse, Box<dyn Error + Send + Sync>> {
-    use crate::{HTTP_BACKEND_URL, REQWEST_CLIENT};
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
 
     let path = format!("{}/turtle/{uuid}/move/", HTTP_BACKEND_URL);
-    let response = REQWEST_CLIENT
-        .put(path)
+    let response = with_api_token(REQWEST_CLIENT.put(path))
         .body(direction.to_string())
         .send()
-        .await?
-        .json::<TurtleMoveResponse>()
         .await?;
 
-    Ok(response)
+    //Backend explains why the turtle cannot move (for example it is out of fuel)
+    if !response.status().is_success() {
+        return Err(response.text().await?.into());
+    }
+
+    Ok(response.json::<TurtleMoveResponse>().await?)
 }
 
 fn recive_notification(
@@ -169,43 +184,13 @@ fn recive_notification(
         Ok(val) => {
             match val {
                 Some(response) => {
-                    let (start_x, start_z, rot_y) =
-                        rotation_to_start_loc(&response.rotation);
-
                     let (mut animation_player, name) = turtle_object_query.single_mut();
-                    let mut animation = AnimationClip::default();
-
-                    animation.add_curve_to_path(
-                        EntityPath {
-                            parts: vec![name.clone()],
-                        },
-                        VariableCurve {
-                            keyframe_timestamps: vec![1.0],
-                            keyframes: Keyframes::Translation(vec![Vec3::new(
-                                start_x + response.x as f32,
-                                response.y as f32 + 0.5,
-                                start_z + response.z as f32,
-                            )]),
-                        },
-                    );
-                    animation.add_curve_to_path(
-                        EntityPath {
-                            parts: vec![name.clone()],
-                        },
-                        VariableCurve {
-                            keyframe_timestamps: vec![1.0],
-                            keyframes: Keyframes::Rotation(vec![Quat::from_euler(
-                                EulerRot::YXZ,
-                                rot_y,
-                                0.0,
-                                0.0,
-                            )]),
-                        },
-                    );
-
-                    animation_player.start_with_transition(
-                        animations.add(animation),
-                        Duration::from_millis(500),
+                    animate_turtle(
+                        &mut animation_player,
+                        name,
+                        &mut animations,
+                        (response.x, response.y, response.z),
+                        &response.rotation,
                     );
 
                     //turtle_transform.translation = Vec3::new(start_x + response.x as f32, response.y as f32 + 0.5, start_z + response.z as f32);
@@ -231,6 +216,97 @@ fn recive_notification(
     };
 }
 
+/// Moves the main turtle when someone else (another tab, a job) moved it
+fn follow_remote_turtle_updates(
+    mut remote_events: EventReader<RemoteTurtleEvent>,
+    main_turtle: Res<MainTurtle>,
+    mut camera_query: Query<&mut PanOrbitCamera, With<MainCamera>>,
+    mut turtle_object_query: Query<(&mut AnimationPlayer, &Name), With<MainTurtleObject>>,
+    mut animations: ResMut<Assets<AnimationClip>>,
+) {
+    for event in remote_events.iter() {
+        let turtle = match &event.0 {
+            TurtleEvent::TurtleUpdate(turtle) => turtle,
+            _ => continue,
+        };
+
+        let mut guard = main_turtle
+            .write()
+            .expect("Cannot lock main turtle, should never happen!");
+        let main_turtle_ref = match guard.as_mut() {
+            Some(val) if val.uuid == turtle.uuid => val,
+            _ => continue,
+        };
+
+        let moved = (main_turtle_ref.x, main_turtle_ref.y, main_turtle_ref.z, &main_turtle_ref.rotation)
+            != (turtle.x, turtle.y, turtle.z, &turtle.rotation);
+        *main_turtle_ref = turtle.clone();
+        drop(guard);
+
+        if !moved {
+            continue;
+        }
+
+        let (mut animation_player, name) = turtle_object_query.single_mut();
+        animate_turtle(
+            &mut animation_player,
+            name,
+            &mut animations,
+            (turtle.x, turtle.y, turtle.z),
+            &turtle.rotation,
+        );
+
+        let mut camera = camera_query.single_mut();
+        camera.force_update = true;
+        camera.focus = Vec3::new(
+            0.5 + turtle.x as f32,
+            0.5 + turtle.y as f32,
+            0.5 + turtle.z as f32,
+        );
+    }
+}
+
+fn animate_turtle(
+    animation_player: &mut AnimationPlayer,
+    name: &Name,
+    animations: &mut Assets<AnimationClip>,
+    (x, y, z): (i32, i32, i32),
+    rotation: &JsonTurtleDirection,
+) {
+    let (start_x, start_z, rot_y) = rotation_to_start_loc(rotation);
+    let mut animation = AnimationClip::default();
+
+    animation.add_curve_to_path(
+        EntityPath {
+            parts: vec![name.clone()],
+        },
+        VariableCurve {
+            keyframe_timestamps: vec![1.0],
+            keyframes: Keyframes::Translation(vec![Vec3::new(
+                start_x + x as f32,
+                y as f32 + 0.5,
+                start_z + z as f32,
+            )]),
+        },
+    );
+    animation.add_curve_to_path(
+        EntityPath {
+            parts: vec![name.clone()],
+        },
+        VariableCurve {
+            keyframe_timestamps: vec![1.0],
+            keyframes: Keyframes::Rotation(vec![Quat::from_euler(
+                EulerRot::YXZ,
+                rot_y,
+                0.0,
+                0.0,
+            )]),
+        },
+    );
+
+    animation_player.start_with_transition(animations.add(animation), Duration::from_millis(500));
+}
+
 fn on_turtle_change(
     mut ev_change: EventReader<SelectTurtleEvent>,
     mut camera_query: Query<&mut PanOrbitCamera, With<MainCamera>>,
@@ -268,5 +344,7 @@ fn rotation_to_start_loc(rot: &JsonTurtleDirection) -> (f32, f32, f32) {
         JsonTurtleDirection::Backward => (1.0, 1.0, std::f32::consts::PI),
         JsonTurtleDirection::Right => (1.0, 0.0, std::f32::consts::PI * 1.5),
         JsonTurtleDirection::Left => (0.0, 1.0, std::f32::consts::PI / 2.0),
+        //Turtle can never face up or down
+        JsonTurtleDirection::Up | JsonTurtleDirection::Down => unreachable!(),
     };
 }
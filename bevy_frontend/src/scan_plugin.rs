@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use shared::WorldChange;
+use std::error::Error;
+use uuid::Uuid;
+
+use crate::{spawn_async, MainTurtle, WorldChangeEvent};
+
+pub struct ScanPlugin;
+
+#[derive(Resource)]
+struct ScanGate {
+    change_sender: Sender<Vec<WorldChange>>,
+    change_reciver: Receiver<Vec<WorldChange>>,
+}
+
+/// R inspects every side of the main turtle, the ones behind and next to it are not scanned by moves
+fn detect_scan_from_keyboard(
+    keys: Res<Input<KeyCode>>,
+    main_turtle: Res<MainTurtle>,
+    gate: Res<ScanGate>,
+) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+
+    let uuid = match &*main_turtle
+        .read()
+        .expect("Cannot lock main turtle, should never happen!")
+    {
+        Some(val) => val.uuid,
+        None => return,
+    };
+
+    let tx = gate.change_sender.clone();
+    spawn_async(async move {
+        match send_scan_request(&uuid).await {
+            Ok(changes) => tx
+                .try_send(changes)
+                .expect("Cannot send scan result to bevy"),
+            Err(err) => log::error!("Cannot send scan request: {err}"),
+        }
+    });
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn send_scan_request(uuid: &Uuid) -> Result<Vec<WorldChange>, Box<dyn Error>> {
+    use gloo_net::http::Request;
+
+    use crate::with_api_token;
+
+    let response = with_api_token(Request::post(&format!("/turtle/{uuid}/scan/")))
+        .send()
+        .await?
+        .json::<Vec<WorldChange>>()
+        .await?;
+
+    Ok(response)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn send_scan_request(uuid: &Uuid) -> Result<Vec<WorldChange>, Box<dyn Error + Send + Sync>> {
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
+
+    let path = format!("{}/turtle/{uuid}/scan/", HTTP_BACKEND_URL);
+    let response = with_api_token(REQWEST_CLIENT.post(path))
+        .send()
+        .await?
+        .json::<Vec<WorldChange>>()
+        .await?;
+
+    Ok(response)
+}
+
+fn detect_scan_response(
+    mut world_change_writer: EventWriter<WorldChangeEvent>,
+    gate: Res<ScanGate>,
+) {
+    while let Ok(changes) = gate.change_reciver.try_recv() {
+        for change in changes {
+            world_change_writer.send(WorldChangeEvent(change));
+        }
+    }
+}
+
+impl Plugin for ScanPlugin {
+    fn build(&self, app: &mut App) {
+        let (tx, rx) = bounded::<Vec<WorldChange>>(8);
+        app.insert_resource(ScanGate {
+            change_sender: tx,
+            change_reciver: rx,
+        })
+        .add_system(detect_scan_from_keyboard)
+        .add_system(detect_scan_response);
+    }
+}
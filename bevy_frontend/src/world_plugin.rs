@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::error::Error;
 
 use bevy::render::mesh::{Indices, MeshVertexAttribute, VertexAttributeValues};
@@ -7,18 +8,23 @@ use block_mesh::ndshape::{ConstShape, ConstShape3u32};
 use block_mesh::{
     greedy_quads, GreedyQuadsBuffer, MergeVoxel, Voxel, VoxelVisibility, RIGHT_HANDED_Y_UP_CONFIG,
 };
+use bevy_mod_raycast::RaycastMesh;
+use bevy_panorbit_camera::PanOrbitCamera;
 use bytes::Bytes;
 use crossbeam_channel::{
-    unbounded, bounded, Receiver, Sender,
+    unbounded, Receiver, Sender,
 };
-use shared::{WorldChangePaletteEnum, WorldChange};
-use shared::world_structure::{ChunkLocation, TurtleVoxel, TurtleWorld, TurtleWorldPalette, TurtleWorldData};
+use shared::{TurtleEvent, WorldChangePaletteEnum, WorldChange};
+use shared::world_structure::{ChunkLocation, TurtleChunk, TurtleVoxel, TurtleWorld, TurtleWorldPalette, TurtleWorldData};
 use uuid::Uuid;
 
 use crate::chunk_material::{ChunkMaterialSingleton, VoxelTerrainMesh};
-use crate::{spawn_async, BlockRaycastSet, SelectTurtleEvent, WorldChangeEvent};
+use crate::{spawn_async, BlockRaycastSet, MainCamera, RemoteTurtleEvent, SelectTurtleEvent, WorldChangeEvent};
 
 static CHUNKS_PER_FRAME_CAP: usize = 4;
+//Chunks around the camera focus that are requested from the backend
+static CHUNK_LOAD_RADIUS: i32 = 3;
+static CHUNK_LOAD_RADIUS_Y: i32 = 2;
 
 pub struct WorldPlugin;
 
@@ -28,12 +34,42 @@ struct WorldChunk {
     location: ChunkLocation
 }
 
+//Marker for the unknown space of a chunk, it is also a WorldChunk
+#[derive(Component)]
+struct FogChunk;
+
+/// Unknown blocks are drawn as translucent fog when it is visible, F toggles it
+#[derive(Resource)]
+struct Fog {
+    visible: bool,
+    material: Handle<StandardMaterial>,
+}
+
+/// Response of a world region request, the region is None if the request failed
+struct WorldRegion {
+    world_name: String,
+    min: ChunkLocation,
+    max: ChunkLocation,
+    region: Option<TurtleWorld>,
+}
+
 #[derive(Resource)]
 struct GlobalWorldGate {
-    get_all_blocks_rx: Receiver<Option<TurtleWorld>>,
-    get_all_blocks_tx: Sender<Option<TurtleWorld>>,
+    world_region_rx: Receiver<WorldRegion>,
+    world_region_tx: Sender<WorldRegion>,
     chunk_load_rx: Receiver<ChunkLocation>,
     chunk_load_tx: Sender<ChunkLocation>,
+    /// Name of the world that is loaded (or being loaded), turtles in the same world share it
+    loaded_world: Option<String>,
+    /// Turtle used to request chunks of the loaded world
+    world_turtle: Option<Uuid>,
+    /// Chunks that were already requested, empty chunks are not sent by the backend so they are here too
+    requested_chunks: HashSet<ChunkLocation>,
+    /// Chunks of region requests that were not answered yet
+    loading_chunks: HashSet<ChunkLocation>,
+    /// Changes of loading chunks, the fetched region could be older than them so they are applied after it
+    pending_changes: Vec<WorldChange>,
+    last_center: Option<ChunkLocation>,
 }
 
 #[derive(Resource, Deref)]
@@ -47,10 +83,10 @@ struct BoolVoxel(TurtleVoxel);
 
 impl Voxel for BoolVoxel {
     fn get_visibility(&self) -> VoxelVisibility {
-        if self.0.id == 0 {
-            VoxelVisibility::Empty
-        } else {
+        if self.0.is_solid() {
             VoxelVisibility::Opaque
+        } else {
+            VoxelVisibility::Empty
         }
     }
 }
@@ -69,33 +105,141 @@ impl From<TurtleVoxel> for BoolVoxel {
     }
 }
 
+//Faces of unknown blocks that touch known air, solid blocks hide them
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[repr(transparent)]
+struct FogVoxel(TurtleVoxel);
+
+impl Voxel for FogVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        if self.0.is_unknown() {
+            VoxelVisibility::Translucent
+        } else if self.0.is_solid() {
+            VoxelVisibility::Opaque
+        } else {
+            VoxelVisibility::Empty
+        }
+    }
+}
+
+impl MergeVoxel for FogVoxel {
+    type MergeValue = Self;
+
+    fn merge_value(&self) -> Self::MergeValue {
+        *self
+    }
+}
+
 // A 16^3 chunk with 1-voxel boundary padding.
 type ChunkShape = ConstShape3u32<18, 18, 18>;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
-        let (tx, rx) = bounded::<Option<TurtleWorld>>(8);
+        let (tx, rx) = unbounded::<WorldRegion>();
         let (chunk_tx, chunk_rx) = unbounded::<ChunkLocation>();
 
         app.insert_resource(GlobalWorldGate {
-            get_all_blocks_rx: rx,
-            get_all_blocks_tx: tx,
+            world_region_rx: rx,
+            world_region_tx: tx,
             chunk_load_rx: chunk_rx,
             chunk_load_tx: chunk_tx,
+            loaded_world: None,
+            world_turtle: None,
+            requested_chunks: HashSet::new(),
+            loading_chunks: HashSet::new(),
+            pending_changes: Vec::new(),
+            last_center: None,
         })
         .insert_resource(GlobalWorld { world: None })
+        .add_startup_system(setup_fog)
+        .add_system(toggle_fog)
         .add_system(turtle_change_listener)
-        .add_system(recive_all_new_world)
-        .add_system(block_change_detect)
-        .add_system(load_chunk_from_queue.after(recive_all_new_world));
+        .add_system(resync_world.after(turtle_change_listener))
+        .add_system(request_chunks_around_camera.after(resync_world))
+        .add_system(recive_world_region)
+        .add_system(block_change_detect.after(recive_world_region))
+        .add_system(load_chunk_from_queue.after(recive_world_region));
+    }
+}
+
+fn setup_fog(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgba(0.7, 0.7, 0.8, 0.2),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        cull_mode: None,
+        ..Default::default()
+    });
+
+    commands.insert_resource(Fog { visible: false, material });
+}
+
+fn toggle_fog(
+    keys: Res<Input<KeyCode>>,
+    mut fog: ResMut<Fog>,
+    mut fog_chunks: Query<&mut Visibility, With<FogChunk>>,
+) {
+    if !keys.just_pressed(KeyCode::F) {
+        return;
+    }
+
+    fog.visible = !fog.visible;
+    for mut visibility in fog_chunks.iter_mut() {
+        *visibility = fog_visibility(fog.visible);
     }
 }
 
+fn fog_visibility(visible: bool) -> Visibility {
+    if visible {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    }
+}
+
+/// # Returns
+/// Mesh of the fog faces, None if the chunk has no unknown block next to air
+fn fog_mesh(chunk: &TurtleChunk) -> Option<Mesh> {
+    let samples = chunk.voxels().map(FogVoxel);
+    let mut buffer = GreedyQuadsBuffer::new(samples.len());
+    greedy_quads(
+        &samples,
+        &ChunkShape {},
+        [0; 3],
+        [17; 3],
+        &RIGHT_HANDED_Y_UP_CONFIG.faces,
+        &mut buffer,
+    );
+
+    let mut indices = Vec::new();
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    for (group, face) in buffer.quads.groups.iter().zip(RIGHT_HANDED_Y_UP_CONFIG.faces.iter()) {
+        //Solid blocks next to unknown ones have faces too, those are in the chunk mesh already
+        for quad in group.iter().filter(|quad| chunk.raw_voxel(&quad.minimum).is_unknown()) {
+            indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
+            positions.extend_from_slice(&face.quad_mesh_positions(quad, 1.0));
+            normals.extend_from_slice(&face.quad_mesh_normals());
+        }
+    }
+
+    if indices.is_empty() {
+        return None;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    Some(mesh)
+}
+
 fn load_chunk_from_queue(
     mut global_world_gate: ResMut<GlobalWorldGate>,
     mut global_world: ResMut<GlobalWorld>,
     mut meshes: ResMut<Assets<Mesh>>,
     material: Res<crate::chunk_material::ChunkMaterialSingleton>,
+    fog: Res<Fog>,
     world_chunks: Query<(Entity, &WorldChunk)>,
     mut commands: Commands,
 ) {
@@ -110,11 +254,12 @@ fn load_chunk_from_queue(
     let mut i = 0usize;
 
     while let Ok(chunk_loc) = global_world_gate.chunk_load_rx.try_recv() {
-        let previous_mesh = world_chunks
+        //Chunk mesh and its fog
+        let previous_meshes = world_chunks
             .iter()
-            .find(|(_, loc)| loc.location == chunk_loc);
+            .filter(|(_, loc)| loc.location == chunk_loc);
 
-        if let Some((previous_mesh, ..)) = previous_mesh {
+        for (previous_mesh, ..) in previous_meshes {
             commands.entity(previous_mesh).despawn();
         }
 
@@ -180,16 +325,29 @@ fn load_chunk_from_queue(
         //let mut material = StandardMaterial::from(Color::RED);
         //material.perceptual_roughness = 0.85;
 
+        let transform = Transform::from_xyz(
+            (chunk_loc.x * 16) as f32 - 1.,
+            (chunk_loc.y * 16) as f32 - 0.5,
+            (chunk_loc.z * 16) as f32 - 1.,
+        );
+
         commands.spawn((MaterialMeshBundle {
             mesh,
             material: (**material).clone(),
-            transform: Transform::from_xyz(
-                (chunk_loc.x * 16) as f32 - 1.,
-                (chunk_loc.y * 16) as f32 - 0.5,
-                (chunk_loc.z * 16) as f32 - 1.,
-            ),
+            transform,
             ..Default::default()
-        }, WorldChunk { location: chunk_loc.clone() }));
+        }, WorldChunk { location: chunk_loc.clone() }, RaycastMesh::<BlockRaycastSet>::default()));
+
+        //Not a RaycastMesh, clicks go through the fog
+        if let Some(fog_mesh) = fog_mesh(chunk) {
+            commands.spawn((PbrBundle {
+                mesh: meshes.add(fog_mesh),
+                material: fog.material.clone(),
+                transform,
+                visibility: fog_visibility(fog.visible),
+                ..Default::default()
+            }, WorldChunk { location: chunk_loc.clone() }, FogChunk));
+        }
 
         i += 1;
         if i == CHUNKS_PER_FRAME_CAP {
@@ -199,33 +357,32 @@ fn load_chunk_from_queue(
     }
 }
 
-fn recive_all_new_world(
-    global_world_gate: ResMut<GlobalWorldGate>,
+fn recive_world_region(
+    mut global_world_gate: ResMut<GlobalWorldGate>,
     mut global_world: ResMut<GlobalWorld>,
-    //mut material_singletone: ResMut<ChunkMaterialSingleton>
 ) {
-    match global_world_gate.get_all_blocks_rx.try_recv() {
-        Ok(val) => {
-            match val {
-                Some(mut world) => {
-                    let (_, world_data) = world.get_fields_mut();
-                    let res = world_data.iter().map(|(loc, _)| loc).try_for_each(|loc| {
-                        global_world_gate.chunk_load_tx.send(loc.clone())
-                    });
-
-                    if let Err(err) = res {
-                        log::error!("Cannot send turtle chunks loc into further processing. Err: {err}");
-                        return;
-                    }
-
-                    global_world.world = Some(world);
-                }
-                None => {
-                    panic!("The global world channel closed! This SHOULD NEVER HAPPEN!");
-                }
-            }
+    while let Ok(response) = global_world_gate.world_region_rx.try_recv() {
+        //Response for a world we already left
+        if global_world_gate.loaded_world.as_ref() != Some(&response.world_name) {
+            continue;
+        }
+
+        let (min, max) = (response.min, response.max);
+        global_world_gate.loading_chunks.retain(|loc| {
+            !(min.x..=max.x).contains(&loc.x) || !(min.y..=max.y).contains(&loc.y) || !(min.z..=max.z).contains(&loc.z)
+        });
+
+        let Some(region) = response.region else {
+            continue;
+        };
+        let world = global_world.world.get_or_insert_with(TurtleWorld::new);
+        let res = world.extend(region).into_iter().try_for_each(|loc| {
+            global_world_gate.chunk_load_tx.send(loc)
+        });
+
+        if let Err(err) = res {
+            log::error!("Cannot send turtle chunks loc into further processing. Err: {err}");
         }
-        Err(_) => return,
     }
 }
 
@@ -233,67 +390,179 @@ fn turtle_change_listener(
     mut commands: Commands,
     mut select_turtle_reader: EventReader<SelectTurtleEvent>,
     world_blocks: Query<Entity, With<WorldChunk>>,
-    global_world_gate: Res<GlobalWorldGate>,
+    mut global_world_gate: ResMut<GlobalWorldGate>,
+    mut global_world: ResMut<GlobalWorld>,
 ) {
     for event in &mut select_turtle_reader {
-        //Clean the world
-        for entity in world_blocks.iter() {
-            commands.entity(entity).despawn();
+        let new_turtle = match &event.0 {
+            Some(val) => val,
+            None => continue,
+        };
+
+        global_world_gate.world_turtle = Some(new_turtle.uuid);
+
+        //Same world, keep what we already have
+        if global_world_gate.loaded_world.as_ref() == Some(&new_turtle.world) {
+            continue;
         }
 
-        match &event.0 {
-            Some(new_turtle) => {
-                let uuid = new_turtle.uuid;
-                let mut tx = global_world_gate.get_all_blocks_tx.clone();
-
-                //-1 becouse idk yet
-                //let (chunk_x, chunk_y, chunk_z) = (new_turtle.x >> 4, (new_turtle.y >> 4) - 1, new_turtle.z >> 4);
-
-                spawn_async(async move {
-                    let resp = send_get_world_request(&uuid).await;
-
-                    match resp {
-                        Ok(response) => {
-                            let world = match TurtleWorld::from_bytes(response) {
-                                Ok(val) => val,
-                                Err(err) => {
-                                    log::error!(
-                                        "Cannot convert backend response into world. Error: {err}"
-                                    );
-                                    tx.try_send(None).expect("Cannot send world result");
-                                    return;
-                                }
-                            };
-
-                            tx.try_send(Some(world))
-                                .expect("Cannot pass world into bevy system");
-                        }
-                        Err(err) => {
-                            log::error!("Something went wrong when fetching world. Error: {err}");
-                            tx.try_send(None).expect("Cannot send world result");
-                        }
-                    }
-                })
+        global_world_gate.loaded_world = Some(new_turtle.world.clone());
+        clear_world(&mut commands, &world_blocks, &mut global_world_gate, &mut global_world);
+    }
+}
+
+/// Events were missed, the loaded world is fetched again from scratch
+fn resync_world(
+    mut commands: Commands,
+    mut remote_events: EventReader<RemoteTurtleEvent>,
+    world_blocks: Query<Entity, With<WorldChunk>>,
+    mut global_world_gate: ResMut<GlobalWorldGate>,
+    mut global_world: ResMut<GlobalWorld>,
+) {
+    let resync = remote_events.iter().any(|event| matches!(event.0, TurtleEvent::Resync));
+    if resync && global_world_gate.loaded_world.is_some() {
+        log::warn!("Missed events from the backend, fetching the world again");
+        clear_world(&mut commands, &world_blocks, &mut global_world_gate, &mut global_world);
+    }
+}
+
+/// Removes every chunk of the loaded world, the chunks around the camera are requested again
+fn clear_world(
+    commands: &mut Commands,
+    world_blocks: &Query<Entity, With<WorldChunk>>,
+    global_world_gate: &mut GlobalWorldGate,
+    global_world: &mut GlobalWorld,
+) {
+    for entity in world_blocks.iter() {
+        commands.entity(entity).despawn();
+    }
+    while global_world_gate.chunk_load_rx.try_recv().is_ok() {}
+
+    global_world_gate.requested_chunks.clear();
+    global_world_gate.loading_chunks.clear();
+    global_world_gate.pending_changes.clear();
+    global_world_gate.last_center = None;
+    global_world.world = Some(TurtleWorld::new());
+}
+
+/// Requests chunks around the camera focus that were not requested yet
+fn request_chunks_around_camera(
+    mut global_world_gate: ResMut<GlobalWorldGate>,
+    camera: Query<&PanOrbitCamera, With<MainCamera>>,
+) {
+    let (uuid, world_name) = match (global_world_gate.world_turtle, &global_world_gate.loaded_world) {
+        (Some(uuid), Some(world_name)) => (uuid, world_name.clone()),
+        _ => return,
+    };
+    let focus = match camera.get_single() {
+        Ok(camera) => camera.focus,
+        Err(_) => return,
+    };
+
+    //Chunk meshes are rendered 0.5 higher, see load_chunk_from_queue
+    let center = match TurtleWorld::get_chunk_loc_from_global_xyz(
+        focus.x.floor() as i32,
+        (focus.y - 0.5).floor() as i32,
+        focus.z.floor() as i32,
+    ) {
+        Ok((loc, ..)) => loc,
+        Err(_) => return,
+    };
+
+    if global_world_gate.last_center.as_ref() == Some(&center) {
+        return;
+    }
+    global_world_gate.last_center = Some(center.clone());
+
+    let mut missing = Vec::new();
+    for x in -CHUNK_LOAD_RADIUS..=CHUNK_LOAD_RADIUS {
+        for y in -CHUNK_LOAD_RADIUS_Y..=CHUNK_LOAD_RADIUS_Y {
+            for z in -CHUNK_LOAD_RADIUS..=CHUNK_LOAD_RADIUS {
+                let y = match i8::try_from(center.y as i32 + y) {
+                    Ok(val) => val,
+                    Err(_) => continue,
+                };
+                let loc = ChunkLocation::xyz(center.x + x, y, center.z + z);
+
+                if !global_world_gate.requested_chunks.contains(&loc) {
+                    missing.push(loc);
+                }
             }
-            None => {}
         }
     }
+
+    if missing.is_empty() {
+        return;
+    }
+
+    let min = ChunkLocation::xyz(
+        missing.iter().map(|loc| loc.x).min().unwrap_or_default(),
+        missing.iter().map(|loc| loc.y).min().unwrap_or_default(),
+        missing.iter().map(|loc| loc.z).min().unwrap_or_default(),
+    );
+    let max = ChunkLocation::xyz(
+        missing.iter().map(|loc| loc.x).max().unwrap_or_default(),
+        missing.iter().map(|loc| loc.y).max().unwrap_or_default(),
+        missing.iter().map(|loc| loc.z).max().unwrap_or_default(),
+    );
+    global_world_gate.requested_chunks.extend(missing.iter().cloned());
+    global_world_gate.loading_chunks.extend(missing);
+
+    let tx = global_world_gate.world_region_tx.clone();
+    spawn_async(async move {
+        let resp = send_get_world_request(&uuid, &min, &max).await;
+
+        let region = match resp {
+            Ok(response) => match TurtleWorld::from_bytes(response) {
+                Ok(region) => Some(region),
+                Err(err) => {
+                    log::error!("Cannot convert backend response into world. Error: {err}");
+                    None
+                }
+            },
+            Err(err) => {
+                log::error!("Something went wrong when fetching world. Error: {err}");
+                None
+            }
+        };
+
+        tx.try_send(WorldRegion { world_name, min, max, region })
+            .expect("Cannot pass world into bevy system");
+    })
+}
+
+fn region_query(min: &ChunkLocation, max: &ChunkLocation) -> String {
+    format!(
+        "min_x={}&min_y={}&min_z={}&max_x={}&max_y={}&max_z={}",
+        min.x, min.y, min.z, max.x, max.y, max.z
+    )
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-async fn send_get_world_request(uuid: &Uuid) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
-    use crate::{HTTP_BACKEND_URL, REQWEST_CLIENT};
+async fn send_get_world_request(
+    uuid: &Uuid,
+    min: &ChunkLocation,
+    max: &ChunkLocation,
+) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+    use crate::{with_api_token, HTTP_BACKEND_URL, REQWEST_CLIENT};
 
-    let path = format!("{}/turtle/{uuid}/world/", HTTP_BACKEND_URL);
-    let response = REQWEST_CLIENT.get(path).send().await?.bytes().await?;
+    let path = format!("{}/turtle/{uuid}/world/?{}", HTTP_BACKEND_URL, region_query(min, max));
+    let response = with_api_token(REQWEST_CLIENT.get(path)).send().await?.bytes().await?;
 
     Ok(response)
 }
 
 #[cfg(target_arch = "wasm32")]
-async fn send_get_world_request(uuid: &Uuid) -> Result<Bytes, Box<dyn Error>> {
+async fn send_get_world_request(
+    uuid: &Uuid,
+    min: &ChunkLocation,
+    max: &ChunkLocation,
+) -> Result<Bytes, Box<dyn Error>> {
     use gloo_net::http::Request;
-    let response = Request::get(&format!("/turtle/{uuid}/world/"))
+
+    use crate::with_api_token;
+
+    let response = with_api_token(Request::get(&format!("/turtle/{uuid}/world/?{}", region_query(min, max))))
         .send()
         .await?
         .binary()
@@ -303,15 +572,33 @@ async fn send_get_world_request(uuid: &Uuid) -> Result<Bytes, Box<dyn Error>> {
     Ok(response)
 }
 
+/// # Returns
+/// False if the palette is missing the entry or entries before the inserted one, the voxel is not changed then
 fn update_voxel_color(
     palette_enum: &WorldChangePaletteEnum,
     palette: &mut TurtleWorldPalette,
     world_data: &mut TurtleWorldData,
     chunk_loc: &ChunkLocation,
     change: &WorldChange
-) {
-    if let shared::WorldChangePaletteEnum::Insert { i, name } = palette_enum {
-        palette.insert(*i, name.clone());
+) -> bool {
+    match palette_enum {
+        shared::WorldChangePaletteEnum::Insert { i, name, tags } => {
+            //A world region fetched after the change could already have it, only its tags could be new
+            if *i == palette.len() {
+                palette.insert(*i, name.clone(), tags.clone());
+            } else if *i < palette.len() {
+                palette.set_tags(*i, tags.clone());
+            } else {
+                log::warn!("Palette entry {i} ({name}) skips {} entries, the palette is out of date", *i - palette.len());
+                return false;
+            }
+        }
+        shared::WorldChangePaletteEnum::GetOld { i } => {
+            if *i >= palette.len() {
+                log::warn!("Palette entry {i} is not known yet, the palette is out of date");
+                return false;
+            }
+        }
     }
 
     let id: u16 = match palette_enum {
@@ -324,44 +611,68 @@ fn update_voxel_color(
         voxel.id = id;
         Ok(())
     }).unwrap();
+    true
 }
 
 fn block_change_detect(
     mut world_change_events: EventReader<WorldChangeEvent>,
     mut global_world: ResMut<GlobalWorld>,
-    global_world_gate: ResMut<GlobalWorldGate>,
+    mut global_world_gate: ResMut<GlobalWorldGate>,
 ) {
-    if world_change_events.is_empty() {
+    if world_change_events.is_empty() && global_world_gate.pending_changes.is_empty() {
         return
     };
 
-    let mut chunks_to_rerender = Vec::<ChunkLocation>::with_capacity(world_change_events.len());
-    let world = global_world.world.as_mut().expect("Cannot get mutable turtle world");
+    //Changes pushed by the backend can arrive while their chunk is still being fetched,
+    //they wait for the fetched region so it does not overwrite them
+    let mut changes = std::mem::take(&mut global_world_gate.pending_changes);
+    changes.extend(world_change_events.iter().map(|event| event.0.clone()));
+    let Some(world) = global_world.world.as_mut() else {
+        global_world_gate.pending_changes = changes;
+        return
+    };
+    let mut chunks_to_rerender = Vec::<ChunkLocation>::with_capacity(changes.len());
     let (palette, world_data) = world.get_fields_mut();
+    let mut palette_outdated = false;
 
-    for change in &mut world_change_events {
-        let change = &change.0;
+    for change in changes {
         let chunk_loc = ChunkLocation::from_global_xyz(change.x, change.y, change.z);
+        if global_world_gate.loading_chunks.contains(&chunk_loc) {
+            global_world_gate.pending_changes.push(change);
+            continue;
+        }
 
-        match &change.action {
+        let updated = match &change.action {
             shared::WorldChangeAction::New(new_block) => {
-                update_voxel_color(&new_block.palette, palette, world_data, &chunk_loc, change);
+                update_voxel_color(&new_block.palette, palette, world_data, &chunk_loc, &change)
             }
             shared::WorldChangeAction::Update(update) => {
-                update_voxel_color(&update.palette, palette, world_data, &chunk_loc, change);
+                update_voxel_color(&update.palette, palette, world_data, &chunk_loc, &change)
             }
             shared::WorldChangeAction::Delete(_) => {
                 let chunk = world_data.force_get_mut_chunk_by_loc(&chunk_loc);
                 chunk.update_voxel_by_global_xyz(change.x, change.y, change.z, |voxel| {
-                    voxel.id = 0;
+                    *voxel = TurtleVoxel::air();
                     Ok(())
                 }).unwrap();
+                true
             }
         };
 
+        if !updated {
+            palette_outdated = true;
+            continue;
+        }
         chunks_to_rerender.push(chunk_loc);
     }
 
+    //Fetched regions bring the whole palette, the skipped blocks come with them
+    if palette_outdated {
+        log::warn!("Requesting the world around the camera again to update the palette");
+        global_world_gate.requested_chunks.clear();
+        global_world_gate.last_center = None;
+    }
+
     log::warn!("TO REM: {:?}", chunks_to_rerender);
 
     chunks_to_rerender.sort();
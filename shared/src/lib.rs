@@ -1,8 +1,8 @@
 pub mod world_structure;
 pub mod static_vec;
 
-use std::str::FromStr;
-use serde::{Serialize, Deserialize};
+use std::{collections::BTreeMap, str::FromStr};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -10,16 +10,20 @@ pub enum JsonTurtleDirection {
     Forward,
     Right,
     Backward,
-    Left
+    Left,
+    Up,
+    Down
 }
 
-impl ToString for JsonTurtleDirection {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for JsonTurtleDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            JsonTurtleDirection::Forward => "forward".to_string(),
-            JsonTurtleDirection::Backward => "backward".to_string(),
-            JsonTurtleDirection::Left => "left".to_string(),
-            JsonTurtleDirection::Right => "right".to_string(),
+            JsonTurtleDirection::Forward => write!(f, "forward"),
+            JsonTurtleDirection::Backward => write!(f, "backward"),
+            JsonTurtleDirection::Left => write!(f, "left"),
+            JsonTurtleDirection::Right => write!(f, "right"),
+            JsonTurtleDirection::Up => write!(f, "up"),
+            JsonTurtleDirection::Down => write!(f, "down"),
         }
     }
 }
@@ -28,11 +32,13 @@ impl FromStr for JsonTurtleDirection {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        return Ok(match s {
+        Ok(match s {
             "forward" => JsonTurtleDirection::Forward,
             "backward" => JsonTurtleDirection::Backward,
             "left" => JsonTurtleDirection::Left,
             "right" => JsonTurtleDirection::Right,
+            "up" => JsonTurtleDirection::Up,
+            "down" => JsonTurtleDirection::Down,
             _ => return Err(())
         })
     }
@@ -43,7 +49,14 @@ impl JsonTurtleDirection {
     /// A tuple (x, y, z)
     /// # Safety 
     /// NEVER CALL THIS FUNCTION WITH MoveDirection::Left OR MoveDirection:Right
+    /// Up and Down do not depend on the turtle rotation
     pub fn to_turtle_move_diff(&self, turtle_rotation: &JsonTurtleDirection) -> (i32, i32, i32) {
+        match self {
+            JsonTurtleDirection::Up => return (0, 1, 0),
+            JsonTurtleDirection::Down => return (0, -1, 0),
+            _ => {}
+        };
+
         match turtle_rotation {
             JsonTurtleDirection::Right => {
                 match self {
@@ -73,9 +86,38 @@ impl JsonTurtleDirection {
                     _ => unreachable!()
                 }
             },
+            //Turtle can never face up or down
+            JsonTurtleDirection::Up | JsonTurtleDirection::Down => unreachable!()
+        }
+    }
+
+    /// Same as [to_turtle_move_diff](JsonTurtleDirection::to_turtle_move_diff) but Left and Right are allowed
+    /// # Returns
+    /// A tuple (x, y, z) pointing at the block on the given side of the turtle
+    pub fn to_turtle_side_diff(&self, turtle_rotation: &JsonTurtleDirection) -> (i32, i32, i32) {
+        match self {
+            JsonTurtleDirection::Left | JsonTurtleDirection::Right => {
+                let mut side_rotation = turtle_rotation.clone();
+                side_rotation.rotate_self(self);
+                JsonTurtleDirection::Forward.to_turtle_move_diff(&side_rotation)
+            },
+            _ => self.to_turtle_move_diff(turtle_rotation)
         }
     }
 
+    /// Reverse of [to_turtle_move_diff](JsonTurtleDirection::to_turtle_move_diff) for a forward move
+    /// # Returns
+    /// Rotation of a turtle that moved by (x, z) going forward, None if that is not a single block move
+    pub fn from_forward_diff(x: i32, z: i32) -> Option<Self> {
+        Some(match (x, z) {
+            (0, -1) => Self::Forward,
+            (1, 0) => Self::Right,
+            (0, 1) => Self::Backward,
+            (-1, 0) => Self::Left,
+            _ => return None
+        })
+    }
+
     fn from_i32(number: i32) -> Self {
         match number {
             0 => Self::Forward,
@@ -91,6 +133,7 @@ impl JsonTurtleDirection {
             Self::Right => 1,
             Self::Backward => 2,
             Self::Left => 3,
+            Self::Up | Self::Down => panic!("Up and down are not valid rotations")
         }
     }
 
@@ -117,18 +160,130 @@ impl JsonTurtleDirection {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub enum TurtleFuel {
+    #[default]
+    Unknown,
+    Unlimited,
+    Limited { level: i64, limit: i64 }
+}
+
+impl std::fmt::Display for TurtleFuel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TurtleFuel::Unknown => write!(f, "?"),
+            TurtleFuel::Unlimited => write!(f, "unlimited"),
+            TurtleFuel::Limited { level, limit } => write!(f, "{level}/{limit}"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct JsonTurtle {
     pub uuid: Uuid, 
     pub x: i32,
     pub y: i32,
     pub z: i32,
-    pub rotation: JsonTurtleDirection 
+    #[serde(deserialize_with = "deserialize_rotation")]
+    pub rotation: JsonTurtleDirection,
+    #[serde(default)]
+    pub fuel: TurtleFuel,
+    /// Name of the shared world (minecraft dimension) the turtle is in
+    #[serde(default = "default_world_name")]
+    pub world: String,
+    /// True when x, y, z and rotation are real minecraft coordinates from the gps api
+    #[serde(default)]
+    pub gps_located: bool,
+    /// Offline turtles are still listed, they can not run any command
+    #[serde(default)]
+    pub online: bool,
+    /// Unix time (seconds) of the last message from the turtle
+    #[serde(default)]
+    pub last_seen: Option<u64>,
+    /// Saved with the turtle so the job continues after a backend restart
+    #[serde(default)]
+    pub excavation: Option<ExcavationJob>
+}
+
+pub const DEFAULT_WORLD_NAME: &str = "overworld";
+
+fn default_world_name() -> String {
+    DEFAULT_WORLD_NAME.to_string()
+}
+
+/// Turtles can never face up or down, turning or moving a turtle with such a rotation panics
+fn deserialize_rotation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<JsonTurtleDirection, D::Error> {
+    match JsonTurtleDirection::deserialize(deserializer)? {
+        JsonTurtleDirection::Up | JsonTurtleDirection::Down => Err(D::Error::custom("turtle rotation can not be up or down")),
+        rotation => Ok(rotation),
+    }
 }
 
+/// Places the turtle in the shared world coordinates
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TurtlePositionRequest {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    pub rotation: JsonTurtleDirection,
+    pub world: Option<String>
+}
+
+/// Result of turtle.inspect
 #[derive(Deserialize)]
 pub struct TurtleBlock {
-    pub name: String
+    pub name: String,
+    /// Facing, liquid level and so on
+    #[serde(default)]
+    pub state: BTreeMap<String, BlockStateValue>,
+    /// Sent as a set, `{ ["minecraft:logs"] = true }`
+    #[serde(default)]
+    pub tags: BTreeMap<String, bool>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum BlockStateValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl std::fmt::Display for BlockStateValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockStateValue::Bool(val) => write!(f, "{val}"),
+            BlockStateValue::Int(val) => write!(f, "{val}"),
+            BlockStateValue::Float(val) => write!(f, "{val}"),
+            BlockStateValue::String(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+impl TurtleBlock {
+    /// Name with the state sorted by key, `minecraft:furnace[facing=north,lit=false]`, palette entries are keyed on it.
+    /// See [split_palette_entry](world_structure::split_palette_entry)
+    pub fn palette_name(&self) -> String {
+        if self.state.is_empty() {
+            return self.name.clone();
+        }
+
+        let state = self.state
+            .iter()
+            .map(|(key, val)| format!("{key}={val}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("{}[{state}]", self.name)
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter(|(_, present)| **present)
+            .map(|(tag, _)| tag.clone())
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -141,14 +296,14 @@ pub struct WorldUpdateBlock {
     pub z: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum WorldChangeAction {
     New(WorldChangeNewBlock),
     Update(WorldChangeUpdateBlock),
     Delete(WorldChangeDeleteBlock)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorldChange {
     pub x: i32,
     pub y: i32,
@@ -156,44 +311,333 @@ pub struct WorldChange {
     pub action: WorldChangeAction,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum WorldChangePaletteEnum {
-    Insert { i: usize, name: String },
+    /// New palette entry, or an old one with changed tags
+    Insert {
+        i: usize,
+        name: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
     GetOld { i: usize }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(transparent)]
 pub struct WorldChangeNewBlock {
     pub palette: WorldChangePaletteEnum 
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(transparent)]
 pub struct WorldChangeUpdateBlock {
     pub palette: WorldChangePaletteEnum 
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorldChangeDeleteBlock();
 
+/// Pushed to every frontend listening on the /events/ websocket
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum TurtleEvent {
+    WorldChange { world: String, change: WorldChange },
+    /// Position, rotation or fuel of the turtle changed
+    TurtleUpdate(JsonTurtle),
+    TurtleConnected(JsonTurtle),
+    TurtleDisconnected { uuid: Uuid },
+    InventoryUpdate { uuid: Uuid, inventory: Vec<Option<TurtleInventoryItem>> },
+    ExplorationProgress { uuid: Uuid, progress: ExplorationProgress },
+    /// The frontend was too slow and missed events, it has to fetch the world and the turtle list again
+    Resync,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TurtleMoveResponse {
     pub x: i32,
     pub y: i32,
     pub z: i32,
+    #[serde(deserialize_with = "deserialize_rotation")]
     pub rotation: JsonTurtleDirection,
+    pub fuel: TurtleFuel,
     pub changes: Vec<WorldChange>
 }
 
-#[derive(Serialize, Deserialize, Debug)] 
+#[derive(Serialize, Deserialize, Debug, Clone)] 
 pub struct TurtleInventoryItem {
     pub name: String,
     pub count: i64,
     pub selected: bool
 }
 
+/// Moves items from slot `from` into slot `to` (1-16). All items are moved when count is None
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransferItemsRequest {
+    pub from: u8,
+    pub to: u8,
+    pub count: Option<u32>
+}
+
+/// Used by both drop and suck. Only Forward, Up and Down are valid sides
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ItemsSideRequest {
+    pub side: JsonTurtleDirection,
+    pub count: Option<u32>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RefuelRequest {
+    pub count: Option<u32>
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DestroyBlockResponse {
     pub change: Option<WorldChange>
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlaceBlockRequest {
+    pub side: JsonTurtleDirection,
+    pub slot: u8
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlaceBlockResponse {
+    pub change: Option<WorldChange>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TurtleQueueResponse {
+    /// Actions that are running or waiting for the turtle
+    pub depth: usize
+}
+
+/// Body of `POST /turtle/:id/explore/`, the turtle never digs while exploring
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExplorationRequest {
+    /// Corners of the explored region (inclusive), the turtle has to start inside it
+    pub from: (i32, i32, i32),
+    pub to: (i32, i32, i32),
+    /// Blocks the turtle may move, turns are free
+    pub max_moves: u32
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ExplorationState {
+    Exploring,
+    /// Every block of the region the turtle can get to is known
+    Explored,
+    OutOfMoves,
+    Failed { reason: String },
+}
+
+/// Sent as [TurtleEvent::ExplorationProgress] after every inspected position, the last one is also the response of the explore request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExplorationProgress {
+    pub moves: u32,
+    pub max_moves: u32,
+    /// Blocks that were inspected
+    pub known: usize,
+    pub state: ExplorationState,
+}
+
+/// Body of `POST /turtle/:id/excavate/`, every block between the two corners (inclusive) is dug
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExcavationRequest {
+    pub from: (i32, i32, i32),
+    pub to: (i32, i32, i32),
+    /// Where the turtle goes when it is full, low on fuel or done. Defaults to where it is now
+    #[serde(default)]
+    pub home: Option<(i32, i32, i32)>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ExcavationState {
+    Running,
+    /// The turtle waits (at home if it went back on its own) until the job is resumed
+    Paused { reason: String },
+    Cancelled,
+    Finished,
+    Failed { reason: String },
+}
+
+/// One position of the excavation, the turtle moves there and digs above and below it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcavationStop {
+    pub position: (i32, i32, i32),
+    pub dig_up: bool,
+    pub dig_down: bool,
+}
+
+/// Excavation of a cuboid, it is dug in passes of three layers from the top down.
+/// Every pass goes through the area in a serpentine so the next stop is always next to the previous one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExcavationJob {
+    pub min: (i32, i32, i32),
+    pub max: (i32, i32, i32),
+    pub home: (i32, i32, i32),
+    /// Stops that are already dug, see [ExcavationJob::stop]
+    pub done: usize,
+    pub total: usize,
+    pub state: ExcavationState,
+}
+
+impl ExcavationJob {
+    /// # Returns
+    /// None if the area has too many blocks to count
+    pub fn new(from: (i32, i32, i32), to: (i32, i32, i32), home: (i32, i32, i32)) -> Option<Self> {
+        let min = (from.0.min(to.0), from.1.min(to.1), from.2.min(to.2));
+        let max = (from.0.max(to.0), from.1.max(to.1), from.2.max(to.2));
+
+        let size_x = usize::try_from(max.0.abs_diff(min.0)).ok()?.checked_add(1)?;
+        let size_z = usize::try_from(max.2.abs_diff(min.2)).ok()?.checked_add(1)?;
+        let passes = usize::try_from(max.1.abs_diff(min.1) / 3).ok()? + 1;
+
+        Some(Self {
+            min,
+            max,
+            home,
+            done: 0,
+            total: size_x.checked_mul(size_z)?.checked_mul(passes)?,
+            state: ExcavationState::Running,
+        })
+    }
+
+    /// # Returns
+    /// Stop with the given index, None after the last one
+    pub fn stop(&self, index: usize) -> Option<ExcavationStop> {
+        if index >= self.total {
+            return None;
+        }
+
+        let size_x = self.max.0.abs_diff(self.min.0) as usize + 1;
+        let size_z = self.max.2.abs_diff(self.min.2) as usize + 1;
+        let per_pass = size_x * size_z;
+
+        let pass = index / per_pass;
+        let row = (index % per_pass) / size_x;
+        let column = index % size_x;
+
+        //Odd passes go back through the rows and every other row goes back through the columns
+        let row = if pass % 2 == 1 { size_z - 1 - row } else { row };
+        let column = if (pass * size_z + row) % 2 == 1 { size_x - 1 - column } else { column };
+
+        let top = self.max.1 - 3 * pass as i32;
+        let bottom = (top - 2).max(self.min.1);
+        let y = (top - 1).max(bottom);
+
+        Some(ExcavationStop {
+            position: (self.min.0 + column as i32, y, self.min.2 + row as i32),
+            dig_up: y < top,
+            dig_down: y > bottom,
+        })
+    }
+
+    pub fn next_stop(&self) -> Option<ExcavationStop> {
+        self.stop(self.done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::{BTreeMap, HashSet}, str::FromStr};
+    use serde::de::{value::Error, IntoDeserializer};
+    use crate::{deserialize_rotation, BlockStateValue, ExcavationJob, JsonTurtleDirection, TurtleBlock};
+    use crate::world_structure::split_palette_entry;
+
+    #[test]
+    fn test_block_palette_name() {
+        let mut block = TurtleBlock {
+            name: "minecraft:furnace".to_string(),
+            state: BTreeMap::new(),
+            tags: BTreeMap::from([("minecraft:mineable/pickaxe".to_string(), true), ("c:ores".to_string(), false)]),
+        };
+        assert_eq!(block.palette_name(), "minecraft:furnace");
+        assert_eq!(block.tags(), vec!["minecraft:mineable/pickaxe".to_string()]);
+
+        block.state.insert("lit".to_string(), BlockStateValue::Bool(false));
+        block.state.insert("facing".to_string(), BlockStateValue::String("north".to_string()));
+        assert_eq!(block.palette_name(), "minecraft:furnace[facing=north,lit=false]");
+        assert_eq!(split_palette_entry(&block.palette_name()), ("minecraft:furnace", Some("facing=north,lit=false")));
+    }
+
+    #[test]
+    fn test_vertical_move_diff() {
+        for rotation in [JsonTurtleDirection::Forward, JsonTurtleDirection::Right, JsonTurtleDirection::Backward, JsonTurtleDirection::Left] {
+            assert_eq!(JsonTurtleDirection::Up.to_turtle_move_diff(&rotation), (0, 1, 0));
+            assert_eq!(JsonTurtleDirection::Down.to_turtle_move_diff(&rotation), (0, -1, 0));
+        }
+
+        assert_eq!(JsonTurtleDirection::from_str("up"), Ok(JsonTurtleDirection::Up));
+        assert_eq!(JsonTurtleDirection::from_str(&JsonTurtleDirection::Down.to_string()), Ok(JsonTurtleDirection::Down));
+    }
+
+    #[test]
+    fn test_side_diff() {
+        let rotation = JsonTurtleDirection::Forward;
+        assert_eq!(JsonTurtleDirection::Right.to_turtle_side_diff(&rotation), (1, 0, 0));
+        assert_eq!(JsonTurtleDirection::Left.to_turtle_side_diff(&rotation), (-1, 0, 0));
+        assert_eq!(JsonTurtleDirection::Backward.to_turtle_side_diff(&rotation), (0, 0, 1));
+
+        let rotation = JsonTurtleDirection::Left;
+        assert_eq!(JsonTurtleDirection::Right.to_turtle_side_diff(&rotation), (0, 0, -1));
+        assert_eq!(JsonTurtleDirection::Down.to_turtle_side_diff(&rotation), (0, -1, 0));
+    }
+
+    #[test]
+    fn test_from_forward_diff() {
+        for rotation in [JsonTurtleDirection::Forward, JsonTurtleDirection::Right, JsonTurtleDirection::Backward, JsonTurtleDirection::Left] {
+            let (x, _, z) = JsonTurtleDirection::Forward.to_turtle_move_diff(&rotation);
+            assert_eq!(JsonTurtleDirection::from_forward_diff(x, z), Some(rotation));
+        }
+
+        assert_eq!(JsonTurtleDirection::from_forward_diff(1, 1), None);
+        assert_eq!(JsonTurtleDirection::from_forward_diff(0, 0), None);
+    }
+
+    #[test]
+    fn test_vertical_rotation_is_rejected() {
+        let rotation = deserialize_rotation(IntoDeserializer::<Error>::into_deserializer("Left"));
+        assert_eq!(rotation, Ok(JsonTurtleDirection::Left));
+
+        for name in ["Up", "Down"] {
+            assert!(deserialize_rotation(IntoDeserializer::<Error>::into_deserializer(name)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_excavation_stops() {
+        for (from, to) in [((0, 0, 0), (3, 6, 2)), ((5, 10, -2), (5, 9, 1)), ((2, 4, 2), (-1, 0, 0))] {
+            let job = ExcavationJob::new(from, to, (0, 0, 0)).unwrap();
+            let stops: Vec<_> = (0..job.total).map(|i| job.stop(i).unwrap()).collect();
+            assert_eq!(job.stop(job.total), None);
+
+            //The turtle never has to go around the area to get to the next stop
+            for pair in stops.windows(2) {
+                let ((x, y, z), (next_x, next_y, next_z)) = (pair[0].position, pair[1].position);
+                if y == next_y {
+                    assert_eq!(x.abs_diff(next_x) + z.abs_diff(next_z), 1);
+                } else {
+                    assert_eq!((x, z), (next_x, next_z));
+                }
+            }
+
+            let mut dug = HashSet::new();
+            for stop in &stops {
+                let (x, y, z) = stop.position;
+                assert!(dug.insert((x, y, z)));
+                if stop.dig_up {
+                    assert!(dug.insert((x, y + 1, z)));
+                }
+                if stop.dig_down {
+                    assert!(dug.insert((x, y - 1, z)));
+                }
+            }
+
+            let (min, max) = (job.min, job.max);
+            let blocks = (max.0 - min.0 + 1) * (max.1 - min.1 + 1) * (max.2 - min.2 + 1);
+            assert_eq!(dug.len(), blocks as usize);
+            assert!(dug.iter().all(|(x, y, z)| (min.0..=max.0).contains(x) && (min.1..=max.1).contains(y) && (min.2..=max.2).contains(z)));
+        }
+    }
+}
@@ -24,6 +24,12 @@ impl<T, const S: usize> StaticVec<T, S> {
     }
 }
 
+impl<T, const S: usize> Default for StaticVec<T, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T, const S: usize> Deref for StaticVec<T, S> {
     type Target = [T; S];
 
@@ -1,4 +1,4 @@
-use std::{collections::{HashMap}, hash::{Hasher, Hash, BuildHasher}, error::Error};
+use std::{collections::{HashMap, HashSet}, hash::Hash, error::Error};
 
 use bytes::{Bytes, BytesMut, BufMut, Buf};
 use bytestring::ByteString;
@@ -8,6 +8,36 @@ use ndshape::{ConstShape3u32, ConstShape};
 pub type ChunkShape = ConstShape3u32<18, 18, 18>;
 pub type RealChunkShape = ConstShape3u32<16, 16, 16>;
 
+//World file header: magic, format version (u16) and payload length (u64), crc32 of the payload is at the end
+static WORLD_MAGIC: &[u8; 4] = b"TWLD";
+//Version 2 split unknown blocks from air, see TurtleVoxel::UNKNOWN_ID. Version 3 added block tags to the palette.
+//Worlds from before the header are read as version 0
+static WORLD_FORMAT_VERSION: u16 = 3;
+static UNKNOWN_BLOCK_NAME: &str = "web_turtle:unknown";
+static AIR_BLOCK_NAME: &str = "minecraft:air";
+
+macro_rules! safe_assert {
+    ($cond:expr) => {
+        if !$cond {
+            return Err(format!("Safe assert failed ({}:{})) Cond: {}", std::file!(), std::line!(), stringify!($cond)).into())
+        }
+    };
+}
+
+macro_rules! assert_len {
+    ($bytes:expr, $len:expr) => {
+        safe_assert!($bytes.remaining() >= $len);
+    };
+}
+
+/// How chunk voxels are stored in the world payload
+enum ChunkEncoding {
+    /// Every voxel as u16, the only encoding before the format was versioned
+    Raw,
+    /// (run length: u16, voxel id: u16) pairs
+    RunLength,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 #[repr(transparent)]
 pub struct TurtleVoxel {
@@ -21,15 +51,20 @@ pub struct ChunkLocation {
     pub z: i32,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Debug)]
 pub struct TurtleWorldData {
     chunks: HashMap<ChunkLocation, TurtleChunk>, 
+    /// Chunks that were handed out mutably since the last [take_dirty](TurtleWorldData::take_dirty)
+    dirty: HashSet<ChunkLocation>,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Debug)]
 pub struct TurtleWorldPalette {
-    palette: Vec<ByteString>,
+    palette: Vec<ByteString>, //Block name with its state, see split_palette_entry
     palette_hashmap: HashMap<String, usize>, //Used to convert name of block into pallete index,
+    tags: Vec<Vec<ByteString>>, //Tags of every palette entry
+    /// Entries that were added or got new tags since the last [take_dirty](TurtleWorldPalette::take_dirty)
+    dirty: HashSet<usize>,
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -60,25 +95,57 @@ impl ChunkLocation {
         Self::xyz(chunk_x, chunk_y, chunk_z)
     }
 
+    /// # Returns
+    /// True if the location is inside the box between min and max (inclusive)
+    pub fn is_within(&self, min: &ChunkLocation, max: &ChunkLocation) -> bool {
+        (min.x..=max.x).contains(&self.x)
+            && (min.y..=max.y).contains(&self.y)
+            && (min.z..=max.z).contains(&self.z)
+    }
+
     ///Padding is left to individual functions
     #[inline(always)]
     fn global_xyz_to_local(&self, x: i32, y: i32, z: i32) -> Result<(u32, u32, u32), Box<dyn Error + Send + Sync>> {
         let (chunk_top_x, chunk_top_z) = (self.x << 4, self.z << 4);
 
         //These are local chunk XYZ
-        let (x, y, z): (u32, u32, u32) = ((x - chunk_top_x).abs().try_into()?, (y - (TryInto::<i32>::try_into(self.y)? << 4)).try_into()?, (z - chunk_top_z).abs().try_into()?);
+        let (x, y, z): (u32, u32, u32) = ((x - chunk_top_x).abs().try_into()?, (y - (i32::from(self.y) << 4)).try_into()?, (z - chunk_top_z).abs().try_into()?);
 
         Ok((x, y, z))
     }
 }
 
 impl TurtleVoxel {
+    /// Palette id of blocks that were never inspected, chunks are filled with it
+    pub const UNKNOWN_ID: u16 = 0;
+    /// Palette id of blocks that were inspected and are empty
+    pub const AIR_ID: u16 = 1;
+
+    pub fn unknown() -> Self {
+        Self {
+            id: Self::UNKNOWN_ID
+        }
+    }
+
     pub fn air() -> Self {
         Self {
-            id: 0
+            id: Self::AIR_ID
         }
     }
 
+    pub fn is_unknown(&self) -> bool {
+        self.id == Self::UNKNOWN_ID
+    }
+
+    pub fn is_air(&self) -> bool {
+        self.id == Self::AIR_ID
+    }
+
+    /// A known block that is not air
+    pub fn is_solid(&self) -> bool {
+        self.id > Self::AIR_ID
+    }
+
     pub fn id(id: u16) -> Self {
         Self {
             id
@@ -93,12 +160,16 @@ impl TurtleVoxel {
 
 impl Default for TurtleWorldPalette {
     fn default() -> Self {
-        let mut palette_hashmap = HashMap::new();
-        palette_hashmap.insert("minecraft:air".to_owned(), 0);
-        Self { 
-            palette_hashmap,
-            palette: vec![ByteString::from_static("minecraft:air")]
-        }
+        Self::from_entries(vec![ByteString::from_static(UNKNOWN_BLOCK_NAME), ByteString::from_static(AIR_BLOCK_NAME)], vec![vec![]; 2])
+    }
+}
+
+/// # Returns
+/// Block name and its state (`facing=north,lit=false`) of a palette entry written by [TurtleBlock::palette_name](crate::TurtleBlock::palette_name)
+pub fn split_palette_entry(entry: &str) -> (&str, Option<&str>) {
+    match entry.split_once('[') {
+        Some((name, state)) => (name, Some(state.strip_suffix(']').unwrap_or(state))),
+        None => (entry, None),
     }
 }
 
@@ -106,22 +177,22 @@ impl TurtleChunk {
     fn new_by_xyz(loc: ChunkLocation) -> Self {
         Self {
             location: loc,
-            data: [TurtleVoxel::air(); ChunkShape::SIZE as usize]
+            data: [TurtleVoxel::unknown(); ChunkShape::SIZE as usize]
         }
     }
 
     pub fn get_mut_block_by_local_xyz(&mut self, x: u32, y: u32, z: u32) -> Option<&mut TurtleVoxel> {
-        return self.data.get_mut(ChunkShape::linearize([x + 1, y + 1, z + 1]) as usize);
+        self.data.get_mut(ChunkShape::linearize([x + 1, y + 1, z + 1]) as usize)
     }
 
     pub fn remove_by_global_xyz(&mut self, x: i32, y: i32, z: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
-        return self.update_voxel_by_global_xyz(x, y, z, |voxel| {
-            if voxel.id == 0 {
+        self.update_voxel_by_global_xyz(x, y, z, |voxel| {
+            if voxel.is_air() {
                 return Err("Given block is already air".into());
             };
-            voxel.id = 0;
+            *voxel = TurtleVoxel::air();
             Ok(())
-        });
+        })
     }
 
     pub fn update_voxel_by_global_xyz<F>(&mut self, x: i32, y: i32, z: i32, mut func: F) -> Result<(), Box<dyn Error + Send + Sync>> 
@@ -129,7 +200,7 @@ impl TurtleChunk {
 
         println!("Working on {:?}", self.location);
         let (x, y, z) = self.location.global_xyz_to_local(x, y, z)?;
-        let data = self.data.get_mut(ChunkShape::linearize([(x + 1).try_into()?, (y + 1).try_into()?, (z + 1).try_into()?]) as usize).ok_or::<String>("Something went really wrong, linearize is out of bounds".into())?;
+        let data = self.data.get_mut(ChunkShape::linearize([x + 1, y + 1, z + 1]) as usize).ok_or::<String>("Something went really wrong, linearize is out of bounds".into())?;
 
         func(data)
     }
@@ -143,156 +214,213 @@ impl TurtleChunk {
     }
 }
 
+impl Default for TurtleWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TurtleWorld {
     pub fn new() -> Self {
         Self {
             pallete: TurtleWorldPalette::default(),
             data: TurtleWorldData { 
-                chunks: HashMap::new()
+                chunks: HashMap::new(),
+                dirty: HashSet::new(),
             },
         }
     }
 
     pub fn to_bytes(&self) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+        let chunks: Vec<&TurtleChunk> = self.data.chunks.values().collect();
+        self.chunks_to_bytes(&chunks)
+    }
+
+    /// Same as [to_bytes](TurtleWorld::to_bytes) but only chunks between min and max (inclusive) are written.
+    /// The whole palette is always written so the result can be read with [from_bytes](TurtleWorld::from_bytes)
+    pub fn region_to_bytes(&self, min: &ChunkLocation, max: &ChunkLocation) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+        let chunks: Vec<&TurtleChunk> = self.data.chunks
+            .values()
+            .filter(|chunk| chunk.location.is_within(min, max))
+            .collect();
+        self.chunks_to_bytes(&chunks)
+    }
+
+    /// Only the palette, stores that keep every chunk on its own read it back with [from_parts](TurtleWorld::from_parts)
+    pub fn palette_to_bytes(&self) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
         let mut bytes = BytesMut::new();
+        self.write_palette(&mut bytes)?;
+        frame_payload(bytes.freeze())
+    }
 
-        macro_rules! write_usize {
-            ($data:expr) => {
-                bytes.reserve(8);
-                bytes.put_u64_le($data.try_into()?);
+    /// Only the given palette entries, they are applied on top of a palette with [apply_palette_entries_bytes](TurtleWorld::apply_palette_entries_bytes)
+    pub fn palette_entries_to_bytes(&self, ids: &[usize]) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+        let mut bytes = BytesMut::new();
+        bytes.reserve(8);
+        bytes.put_u64_le(ids.len().try_into()?);
+        for id in ids {
+            let (Some(name), Some(tags)) = (self.pallete.palette.get(*id), self.pallete.tags.get(*id)) else {
+                return Err(format!("Palette entry {id} does not exist").into());
             };
+
+            bytes.reserve(8);
+            bytes.put_u64_le((*id).try_into()?);
+            write_string(name, &mut bytes)?;
+            bytes.reserve(8);
+            bytes.put_u64_le(tags.len().try_into()?);
+            for tag in tags {
+                write_string(tag, &mut bytes)?;
+            }
         }
+        frame_payload(bytes.freeze())
+    }
 
-        macro_rules! write_slice {
-            ($data:expr) => {
-                {
-                    bytes.reserve(8 + $data.len());
-                    bytes.put_u64_le($data.len().try_into()?);
-                    bytes.put_slice($data);
-                }
+    /// # Returns
+    /// None if the chunk was never loaded
+    pub fn chunk_to_bytes(&self, loc: &ChunkLocation) -> Result<Option<Bytes>, Box<dyn Error + Send + Sync>> {
+        let chunk = match self.data.chunks.get(loc) {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
 
-            };
+        let mut bytes = BytesMut::new();
+        write_chunk(chunk, &mut bytes)?;
+        Ok(Some(frame_payload(bytes.freeze())?))
+    }
+
+    fn chunks_to_bytes(&self, chunks: &[&TurtleChunk]) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+        let mut bytes = BytesMut::new();
+
+        self.write_palette(&mut bytes)?;
+        bytes.reserve(8);
+        bytes.put_u64_le(chunks.len().try_into()?);
+        for chunk in chunks {
+            write_chunk(chunk, &mut bytes)?;
         }
 
-        write_usize!(self.pallete.palette.len());
+        frame_payload(bytes.freeze())
+    }
+
+    /// Block names first, the same way as before tags were added, then the tags of every entry
+    fn write_palette(&self, bytes: &mut BytesMut) -> Result<(), Box<dyn Error + Send + Sync>> {
+        bytes.reserve(8);
+        bytes.put_u64_le(self.pallete.palette.len().try_into()?);
         for block_name in &self.pallete.palette {
-            write_slice!(str::as_bytes(&block_name))
-        }
-        write_usize!(self.data.chunks.len());
-
-        for (_, chunk) in &self.data.chunks {
-            bytes.reserve(9);
-            bytes.put_i32_le(chunk.location.x);
-            bytes.put_i8(chunk.location.y);
-            bytes.put_i32_le(chunk.location.z);
-
-            let mut real_data = [TurtleVoxel::air(); RealChunkShape::SIZE as usize];
-            copy3([16; 3], &chunk.data, &ChunkShape {}, [1; 3], &mut real_data, &RealChunkShape {}, [0; 3]);
-            let real_data: Vec<u8> = real_data
-                .iter()
-                .flat_map(|x| x.id.to_le_bytes())
-                .collect();
-
-            write_slice!(&real_data);
+            write_string(block_name, bytes)?;
         }
 
-        Ok(bytes.freeze())
+        for tags in &self.pallete.tags {
+            bytes.reserve(8);
+            bytes.put_u64_le(tags.len().try_into()?);
+            for tag in tags {
+                write_string(tag, bytes)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn from_bytes(mut bytes: Bytes) -> Result<Self, Box<dyn Error + Send + Sync>>{
+    /// Reads a world written by [to_bytes](TurtleWorld::to_bytes).
+    /// Worlds saved before the format was versioned (no magic) are still readable, they are migrated on the next save
+    pub fn from_bytes(bytes: Bytes) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if !bytes.starts_with(WORLD_MAGIC) {
+            return Self::read_payload(bytes, ChunkEncoding::Raw, 0);
+        }
 
-        macro_rules! safe_assert {
-            ($cond:expr) => {
-                if !$cond {
-                    return Err(format!("Safe assert failed ({}:{})) Cond: {}", std::file!(), std::line!(), stringify!($cond)).into())
-                }
-            };
+        let (version, payload) = unframe_payload(bytes)?;
+        Self::read_payload(payload, ChunkEncoding::RunLength, version)
+    }
+
+    /// Builds a world from [palette_to_bytes](TurtleWorld::palette_to_bytes) and [chunk_to_bytes](TurtleWorld::chunk_to_bytes)
+    pub fn from_parts<I: IntoIterator<Item = Bytes>>(palette: Bytes, chunks: I) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut world = Self::new();
+        world.replace_palette_bytes(palette)?;
+        for chunk in chunks {
+            world.insert_chunk_bytes(chunk)?;
         }
 
-        macro_rules! assert_len {
-            ($len:expr) => {
-                safe_assert!(bytes.remaining() >= $len);
-            };
+        Ok(world)
+    }
+
+    /// Replaces the palette with one written by [palette_to_bytes](TurtleWorld::palette_to_bytes), the palette only ever grows
+    pub fn replace_palette_bytes(&mut self, bytes: Bytes) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (version, mut palette_bytes) = unframe_payload(bytes)?;
+        let palette = read_palette(&mut palette_bytes, version)?;
+        safe_assert!(!palette_bytes.has_remaining());
+        safe_assert!(palette.len() >= self.pallete.len());
+
+        self.pallete = palette;
+        Ok(())
+    }
+
+    /// Adds the entries written by [palette_entries_to_bytes](TurtleWorld::palette_entries_to_bytes), entries that exist get the new tags.
+    /// New entries have to follow the end of the palette
+    pub fn apply_palette_entries_bytes(&mut self, bytes: Bytes) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (_, mut entries_bytes) = unframe_payload(bytes)?;
+        assert_len!(entries_bytes, 8);
+        let entries_len = entries_bytes.get_u64_le();
+
+        for _ in 0..entries_len {
+            assert_len!(entries_bytes, 8);
+            let id: usize = entries_bytes.get_u64_le().try_into()?;
+            let name = read_string(&mut entries_bytes)?;
+            assert_len!(entries_bytes, 8);
+            let tags_len = entries_bytes.get_u64_le();
+            let tags = (0..tags_len)
+                .map(|_| read_string(&mut entries_bytes))
+                .collect::<Result<Vec<ByteString>, Box<dyn Error + Send + Sync>>>()?;
+
+            let palette = &mut self.pallete;
+            if id == palette.palette.len() {
+                safe_assert!(!palette.palette_hashmap.contains_key(&*name));
+                palette.palette_hashmap.insert(name.to_string(), id);
+                palette.palette.push(name);
+                palette.tags.push(tags);
+            } else {
+                safe_assert!(palette.palette.get(id) == Some(&name));
+                palette.tags[id] = tags;
+            }
         }
-       
-        assert_len!(8);
-        let pallete_len = bytes.get_u64_le().try_into()?;
-        let pallete: Result<Vec<ByteString>, String> = (0..pallete_len)
-            .into_iter()
-            .map(|_| {
-                assert_len!(8);
-                let len = bytes.get_u64_le() as usize;
-                let bytes_read = bytes.len() - bytes.remaining();
-                assert_len!(len);
-                let byte_string = unsafe {
-                    let to_ret = ByteString::from_bytes_unchecked(bytes.slice(bytes_read..(bytes_read + len)));
-                    bytes.advance(len);
-                    Ok(to_ret)
-                };
-
-                byte_string
-            })
-            .collect();
-        let palette = pallete?;
+        safe_assert!(!entries_bytes.has_remaining());
 
-        let palette_hashmap = palette
-            .iter()
-            .enumerate()
-            .map(|(id, string)| {
-                (str::to_owned(string), id)
-            })
-            .collect();
+        Ok(())
+    }
 
-        assert_len!(8);
-        let chunks_len = bytes.get_i64_le().try_into()?;
-        let chunks: Result<HashMap<ChunkLocation, TurtleChunk>, String> = (0..chunks_len)
-            .into_iter()
+    /// Adds (or replaces) a chunk written by [chunk_to_bytes](TurtleWorld::chunk_to_bytes), its palette has to be loaded first
+    pub fn insert_chunk_bytes(&mut self, bytes: Bytes) -> Result<ChunkLocation, Box<dyn Error + Send + Sync>> {
+        let (version, mut chunk_bytes) = unframe_payload(bytes)?;
+        let chunk = read_chunk(&mut chunk_bytes, &ChunkEncoding::RunLength, version < 2, self.pallete.len())?;
+        safe_assert!(!chunk_bytes.has_remaining());
+
+        let location = chunk.location.clone();
+        self.data.chunks.insert(location.clone(), chunk);
+        Ok(location)
+    }
+
+    fn read_payload(mut bytes: Bytes, encoding: ChunkEncoding, version: u16) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let palette = read_palette(&mut bytes, version)?;
+
+        assert_len!(bytes, 8);
+        let chunks_len = bytes.get_u64_le();
+        let chunks = (0..chunks_len)
             .map(|_| {
-                assert_len!(9);
-                let x = bytes.get_i32_le();
-                let y = bytes.get_i8();
-                let z = bytes.get_i32_le();
-
-                let data_len = bytes.get_u64_le() as usize;
-                let bytes_read = bytes.len() - bytes.remaining();
-                assert!(bytes_read + data_len <= bytes.len());
-                let data = bytes.slice(bytes_read..(bytes_read + data_len));
-                bytes.advance(data_len);
-
-                let location = ChunkLocation {
-                    x,
-                    y,
-                    z
-                };
-
-                let data_read: Vec<TurtleVoxel> = data
-                    .chunks(2)
-                    .map(|data| u16::from_le_bytes([data[0], data[1]]))
-                    .map(|data| TurtleVoxel::id(data))
-                    .collect();
-
-                let mut final_data = [TurtleVoxel::air(); ChunkShape::SIZE as usize];
-
-                copy3([16; 3], &data_read, &RealChunkShape {}, [0; 3], &mut final_data, &ChunkShape {}, [1; 3]);
-
-                Ok((location.clone(), TurtleChunk {
-                    location,
-                    data: final_data
-                }))
+                let chunk = read_chunk(&mut bytes, &encoding, version < 2, palette.len())?;
+                Ok((chunk.location.clone(), chunk))
             })
-            .collect();
+            .collect::<Result<HashMap<ChunkLocation, TurtleChunk>, Box<dyn Error + Send + Sync>>>()?;
+
+        safe_assert!(!bytes.has_remaining());
 
-        let chunks = chunks?;
+        Ok(Self::from_palette_and_chunks(palette, chunks))
+    }
 
-        Ok(Self {
+    fn from_palette_and_chunks(pallete: TurtleWorldPalette, chunks: HashMap<ChunkLocation, TurtleChunk>) -> Self {
+        Self {
             data: TurtleWorldData {
-                chunks
+                chunks,
+                dirty: HashSet::new(),
             },
-            pallete: TurtleWorldPalette {
-                palette,
-                palette_hashmap
-            }
-        })
+            pallete
+        }
     }
 
     pub fn get_chunk_loc_from_global_xyz(x: i32, y: i32, z: i32) -> Result<(ChunkLocation, u32, u32, u32), Box<dyn Error + Send + Sync>> {
@@ -304,7 +432,33 @@ impl TurtleWorld {
         let (chunk_x, chunk_z) = (x >> 4, z >> 4);
         let chunk_loc = ChunkLocation::xyz(chunk_x, chunk_y, chunk_z);
         let (x, y, z) = chunk_loc.global_xyz_to_local(x, y, z)?;
-        return Ok((chunk_loc, x, y, z))
+        Ok((chunk_loc, x, y, z))
+    }
+
+    /// Adds chunks of a world region (see [region_to_bytes](TurtleWorld::region_to_bytes)) to this world.
+    /// The region palette replaces ours when it is newer, palette only ever grows
+    /// # Returns
+    /// Locations of all added chunks
+    pub fn extend(&mut self, region: TurtleWorld) -> Vec<ChunkLocation> {
+        if region.pallete.len() >= self.pallete.len() {
+            self.pallete = region.pallete;
+        }
+
+        region.data.chunks
+            .into_iter()
+            .map(|(loc, chunk)| {
+                self.data.chunks.insert(loc.clone(), chunk);
+                loc
+            })
+            .collect()
+    }
+
+    /// # Returns
+    /// None if the chunk of the block was never loaded
+    pub fn get_voxel_by_global_xyz(&self, x: i32, y: i32, z: i32) -> Option<TurtleVoxel> {
+        let (loc, x, y, z) = Self::get_chunk_loc_from_global_xyz(x, y, z).ok()?;
+        let chunk = self.data.chunks.get(&loc)?;
+        Some(*chunk.raw_voxel(&[x + 1, y + 1, z + 1]))
     }
 
     pub fn get_fields_mut(&mut self) -> (&mut TurtleWorldPalette, &mut TurtleWorldData) {
@@ -313,34 +467,101 @@ impl TurtleWorld {
     }
 }
 
+/// Only the chunks are compared, dirty chunks are not part of the world
+impl PartialEq for TurtleWorldData {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunks == other.chunks
+    }
+}
+
+impl Eq for TurtleWorldData {}
+
 impl TurtleWorldData {
+    /// Marks the chunk as dirty
     pub fn get_mut_chunk_by_loc(&mut self, loc: &ChunkLocation) -> Option<&mut TurtleChunk> {
-        self.chunks.get_mut(loc)
+        let chunk = self.chunks.get_mut(loc)?;
+        self.dirty.insert(loc.clone());
+        Some(chunk)
     }
 
+    /// Marks the chunk as dirty
     pub fn force_get_mut_chunk_by_loc(&mut self, loc: &ChunkLocation) -> &mut TurtleChunk {
+        self.dirty.insert(loc.clone());
         self.chunks.entry(loc.clone()).or_insert(TurtleChunk::new_by_xyz(loc.clone()))
     }
 
+    /// # Returns
+    /// Chunks that could have changed since the last call, sorted
+    pub fn take_dirty(&mut self) -> Vec<ChunkLocation> {
+        let mut dirty: Vec<ChunkLocation> = self.dirty.drain().collect();
+        dirty.sort();
+        dirty
+    }
+
+    /// Marks the chunks as dirty again, for example when saving them failed
+    pub fn mark_dirty<I: IntoIterator<Item = ChunkLocation>>(&mut self, locs: I) {
+        self.dirty.extend(locs);
+    }
+
     pub fn remove_global_block_by_xyz(&mut self, x: i32, y: i32, z: i32) -> Result<(), Box<dyn Error + Send + Sync>> {
         let chunk_y: i8 = (y >> 4).try_into()?;
 
         let (chunk_x, chunk_z) = (x >> 4, z >> 4);
         let chunk_loc = ChunkLocation::xyz(chunk_x, chunk_y, chunk_z);
 
-        let chunk = self.chunks.get_mut(&chunk_loc).ok_or("Given block does not exist (chunk_err)".to_owned())?;
-        return chunk.remove_by_global_xyz(x, y, z);
+        let chunk = self.get_mut_chunk_by_loc(&chunk_loc).ok_or("Given block does not exist (chunk_err)".to_owned())?;
+        chunk.remove_by_global_xyz(x, y, z)
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<ChunkLocation, TurtleChunk> {
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, ChunkLocation, TurtleChunk> {
         self.chunks.iter()
     }
 }
 
 
 impl TurtleWorldPalette {
+    fn from_entries(palette: Vec<ByteString>, tags: Vec<Vec<ByteString>>) -> Self {
+        let palette_hashmap = palette
+            .iter()
+            .enumerate()
+            .map(|(id, string)| {
+                (str::to_owned(string), id)
+            })
+            .collect();
+
+        Self {
+            palette,
+            palette_hashmap,
+            tags,
+            dirty: HashSet::new(),
+        }
+    }
+
     pub fn get_pallete_from_id(&self, id: u16) -> Option<ByteString> {
-        return self.palette.get(id as usize).cloned()
+        self.palette.get(id as usize).cloned()
+    }
+
+    pub fn get_tags_from_id(&self, id: u16) -> Option<&[ByteString]> {
+        self.tags.get(id as usize).map(Vec::as_slice)
+    }
+
+    /// Same as [get_pallete_index](TurtleWorldPalette::get_pallete_index), tags of an existing entry are replaced when they differ
+    /// # Returns
+    /// Palette id and if the entry is new or its tags changed
+    #[must_use]
+    pub fn get_pallete_index_with_tags(&mut self, item: &str, tags: &[String]) -> (usize, bool) {
+        let (id, new_id) = self.get_pallete_index(item);
+        if self.tags[id].iter().map(|tag| &**tag).eq(tags.iter().map(String::as_str)) {
+            return (id, new_id);
+        }
+
+        self.set_tags(id, tags.to_vec());
+        (id, true)
+    }
+
+    pub fn set_tags(&mut self, id: usize, tags: Vec<String>) {
+        self.tags[id] = tags.into_iter().map(into_byte_string).collect();
+        self.dirty.insert(id);
     }
 
     #[must_use]
@@ -351,13 +572,15 @@ impl TurtleWorldPalette {
                 let id = self.palette.len();
                 self.palette.push(into_byte_string(item.into()));
                 self.palette_hashmap.insert(item.into(), id);
+                self.tags.push(vec![]);
+                self.dirty.insert(id);
                 (id, true)
             },
         }
     }
 
 
-    pub fn insert(&mut self, id: usize, name: String) {
+    pub fn insert(&mut self, id: usize, name: String, tags: Vec<String>) {
         if self.palette.len() != id || self.palette_hashmap.len() != id {
             panic!("Invalid pallete insert! (Len: ({}, {}) but requested id: {})", self.palette.len(), self.palette_hashmap.len(), id);
         }
@@ -365,6 +588,8 @@ impl TurtleWorldPalette {
         let new_id = self.palette.len();
         self.palette.push(into_byte_string(name.clone()));
         self.palette_hashmap.insert(name, new_id);
+        self.tags.push(tags.into_iter().map(into_byte_string).collect());
+        self.dirty.insert(new_id);
     }
 
     pub fn iter(&self) -> std::slice::Iter<'_, ByteString> {
@@ -374,6 +599,222 @@ impl TurtleWorldPalette {
     pub fn len(&self) -> usize {
         self.palette.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.palette.is_empty()
+    }
+
+    /// # Returns
+    /// Entries that could have changed since the last call, sorted
+    pub fn take_dirty(&mut self) -> Vec<usize> {
+        let mut dirty: Vec<usize> = self.dirty.drain().collect();
+        dirty.sort();
+        dirty
+    }
+
+    /// Marks the entries as dirty again, for example when saving them failed
+    pub fn mark_dirty<I: IntoIterator<Item = usize>>(&mut self, ids: I) {
+        self.dirty.extend(ids);
+    }
+}
+
+/// Only the entries are compared, dirty entries are not part of the palette
+impl PartialEq for TurtleWorldPalette {
+    fn eq(&self, other: &Self) -> bool {
+        self.palette == other.palette && self.tags == other.tags
+    }
+}
+
+impl Eq for TurtleWorldPalette {}
+
+/// Adds the magic, format version, payload length and the checksum
+fn frame_payload(payload: Bytes) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+    let mut framed = BytesMut::with_capacity(WORLD_MAGIC.len() + 2 + 8 + payload.len() + 4);
+    framed.put_slice(WORLD_MAGIC);
+    framed.put_u16_le(WORLD_FORMAT_VERSION);
+    framed.put_u64_le(payload.len().try_into()?);
+    framed.put_slice(&payload);
+    framed.put_u32_le(crc32(&payload));
+
+    Ok(framed.freeze())
+}
+
+/// # Returns
+/// Format version and payload of a [frame_payload] result after checking its header and checksum
+fn unframe_payload(mut bytes: Bytes) -> Result<(u16, Bytes), Box<dyn Error + Send + Sync>> {
+    if !bytes.starts_with(WORLD_MAGIC) {
+        return Err("World magic is missing".into());
+    }
+
+    bytes.advance(WORLD_MAGIC.len());
+    if bytes.remaining() < 2 + 8 {
+        return Err("World header is too short".into());
+    }
+
+    let version = bytes.get_u16_le();
+    if !(1..=WORLD_FORMAT_VERSION).contains(&version) {
+        return Err(format!("Unsupported world format version {version}").into());
+    }
+
+    let payload_len: usize = bytes.get_u64_le().try_into()?;
+    if bytes.remaining().checked_sub(4) != Some(payload_len) {
+        return Err("World payload length does not match the header".into());
+    }
+
+    let payload = bytes.split_to(payload_len);
+    let checksum = bytes.get_u32_le();
+    if crc32(&payload) != checksum {
+        return Err("World checksum does not match, the data is corrupted".into());
+    }
+
+    Ok((version, payload))
+}
+
+fn write_chunk(chunk: &TurtleChunk, bytes: &mut BytesMut) -> Result<(), Box<dyn Error + Send + Sync>> {
+    bytes.reserve(9);
+    bytes.put_i32_le(chunk.location.x);
+    bytes.put_i8(chunk.location.y);
+    bytes.put_i32_le(chunk.location.z);
+
+    let mut real_data = [TurtleVoxel::unknown(); RealChunkShape::SIZE as usize];
+    copy3([16; 3], &chunk.data, &ChunkShape {}, [1; 3], &mut real_data, &RealChunkShape {}, [0; 3]);
+
+    let runs = run_length_encode(&real_data);
+    bytes.reserve(4 + runs.len() * 4);
+    bytes.put_u32_le(runs.len().try_into()?);
+    for (len, voxel) in runs {
+        bytes.put_u16_le(len);
+        bytes.put_u16_le(voxel.id);
+    }
+    Ok(())
+}
+
+fn write_string(string: &str, bytes: &mut BytesMut) -> Result<(), Box<dyn Error + Send + Sync>> {
+    bytes.reserve(8 + string.len());
+    bytes.put_u64_le(string.len().try_into()?);
+    bytes.put_slice(string.as_bytes());
+    Ok(())
+}
+
+fn read_string(bytes: &mut Bytes) -> Result<ByteString, Box<dyn Error + Send + Sync>> {
+    assert_len!(bytes, 8);
+    let len: usize = bytes.get_u64_le().try_into().map_err(|_| "Palette entry is too long".to_string())?;
+    assert_len!(bytes, len);
+
+    ByteString::try_from(bytes.split_to(len)).map_err(|_| "Palette entry is not UTF-8".into())
+}
+
+/// Palettes before version 2 start with air that was also used for unknown blocks, unknown is added in front of it.
+/// Palettes before version 3 have no tags
+fn read_palette(bytes: &mut Bytes, version: u16) -> Result<TurtleWorldPalette, Box<dyn Error + Send + Sync>> {
+    assert_len!(bytes, 8);
+    let palette_len = bytes.get_u64_le();
+    let mut palette = (0..palette_len)
+        .map(|_| read_string(bytes))
+        .collect::<Result<Vec<ByteString>, Box<dyn Error + Send + Sync>>>()?;
+
+    let mut tags = match version {
+        0..=2 => vec![vec![]; palette.len()],
+        _ => (0..palette.len())
+            .map(|_| {
+                assert_len!(bytes, 8);
+                let tags_len = bytes.get_u64_le();
+                (0..tags_len).map(|_| read_string(bytes)).collect()
+            })
+            .collect::<Result<Vec<Vec<ByteString>>, Box<dyn Error + Send + Sync>>>()?,
+    };
+
+    safe_assert!(!palette.is_empty());
+    if version < 2 {
+        palette.insert(0, ByteString::from_static(UNKNOWN_BLOCK_NAME));
+        tags.insert(0, vec![]);
+    }
+
+    safe_assert!(palette.len() >= 2);
+    Ok(TurtleWorldPalette::from_entries(palette, tags))
+}
+
+/// Legacy air (0) could also be a block that was never inspected so it becomes unknown, every other id moves up by one
+fn read_chunk(bytes: &mut Bytes, encoding: &ChunkEncoding, legacy_ids: bool, palette_len: usize) -> Result<TurtleChunk, Box<dyn Error + Send + Sync>> {
+    assert_len!(bytes, 9);
+    let x = bytes.get_i32_le();
+    let y = bytes.get_i8();
+    let z = bytes.get_i32_le();
+
+    let data_read: Vec<TurtleVoxel> = match encoding {
+        ChunkEncoding::Raw => {
+            assert_len!(bytes, 8);
+            let data_len = bytes.get_u64_le();
+            safe_assert!(data_len == RealChunkShape::SIZE as u64 * 2);
+            assert_len!(bytes, data_len as usize);
+
+            (0..RealChunkShape::SIZE)
+                .map(|_| TurtleVoxel::id(bytes.get_u16_le()))
+                .collect()
+        },
+        ChunkEncoding::RunLength => {
+            assert_len!(bytes, 4);
+            let runs_len = bytes.get_u32_le() as usize;
+            safe_assert!(runs_len <= RealChunkShape::SIZE as usize);
+            assert_len!(bytes, runs_len * 4);
+
+            let mut data_read = Vec::with_capacity(RealChunkShape::SIZE as usize);
+            for _ in 0..runs_len {
+                let len = bytes.get_u16_le() as usize;
+                let voxel = TurtleVoxel::id(bytes.get_u16_le());
+                safe_assert!(data_read.len() + len <= RealChunkShape::SIZE as usize);
+                data_read.extend(std::iter::repeat_n(voxel, len));
+            }
+            data_read
+        },
+    };
+
+    let data_read: Vec<TurtleVoxel> = match legacy_ids {
+        true => data_read
+            .into_iter()
+            .map(|voxel| match voxel.id {
+                0 => Ok(TurtleVoxel::unknown()),
+                id => id.checked_add(1).map(TurtleVoxel::id).ok_or("Legacy voxel id is too big"),
+            })
+            .collect::<Result<_, _>>()?,
+        false => data_read,
+    };
+
+    safe_assert!(data_read.len() == RealChunkShape::SIZE as usize);
+    safe_assert!(data_read.iter().all(|voxel| (voxel.id as usize) < palette_len));
+
+    let mut final_data = [TurtleVoxel::unknown(); ChunkShape::SIZE as usize];
+    copy3([16; 3], &data_read, &RealChunkShape {}, [0; 3], &mut final_data, &ChunkShape {}, [1; 3]);
+
+    Ok(TurtleChunk {
+        location: ChunkLocation { x, y, z },
+        data: final_data
+    })
+}
+
+/// Chunks are mostly unknown or air so they are stored as runs of the same voxel
+fn run_length_encode(voxels: &[TurtleVoxel]) -> Vec<(u16, TurtleVoxel)> {
+    let mut runs: Vec<(u16, TurtleVoxel)> = Vec::new();
+    for voxel in voxels {
+        match runs.last_mut() {
+            Some((len, last)) if last == voxel && *len < u16::MAX => *len += 1,
+            _ => runs.push((1, *voxel)),
+        }
+    }
+    runs
+}
+
+/// CRC-32 (IEEE) used as the world checksum
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
 }
 
 #[inline(always)]
@@ -385,25 +826,99 @@ pub fn into_byte_string(data: String) -> ByteString {
 
 #[cfg(test)]
 mod tests {
-    use crate::world_structure::{TurtleWorld, TurtleChunk};
+    use crate::world_structure::{TurtleWorld, TurtleChunk, TurtleWorldPalette};
     use crate::world_structure::TurtleVoxel;
     use crate::world_structure::ChunkShape;
     use crate::world_structure::ChunkLocation;
+    use crate::world_structure::{crc32, frame_payload, split_palette_entry, write_chunk, RealChunkShape};
+    use bytes::{BufMut, Bytes, BytesMut};
     use ndshape::ConstShape;
 
+    fn test_world() -> TurtleWorld {
+        let mut world = TurtleWorld::new();
+
+        let (palette, world_data) = world.get_fields_mut();
+        let (stone, _) = palette.get_pallete_index("minecraft:stone");
+        let (dirt, _) = palette.get_pallete_index("minecraft:dirt");
+        for (i, loc) in [ChunkLocation::xyz(0, 0, 0), ChunkLocation::xyz(-3, 4, 7)].into_iter().enumerate() {
+            let chunk = world_data.force_get_mut_chunk_by_loc(&loc);
+            for y in 0..(4 + i as u32) {
+                *chunk.get_mut_block_by_local_xyz(3, y, 5).unwrap() = TurtleVoxel::id(stone as u16);
+                *chunk.get_mut_block_by_local_xyz(y, 0, 0).unwrap() = TurtleVoxel::id(dirt as u16);
+            }
+        }
+
+        world
+    }
+
+    /// Ids used before unknown blocks were split from air, both of them were 0
+    fn to_legacy_ids(world: &TurtleWorld) -> TurtleWorld {
+        let mut legacy = TurtleWorld::new();
+        legacy.pallete = TurtleWorldPalette::from_entries(world.pallete.palette[1..].to_vec(), world.pallete.tags[1..].to_vec());
+        for (loc, chunk) in &world.data.chunks {
+            let legacy_chunk = legacy.data.force_get_mut_chunk_by_loc(loc);
+            for (legacy_voxel, voxel) in legacy_chunk.data.iter_mut().zip(chunk.data.iter()) {
+                legacy_voxel.id = voxel.id.saturating_sub(1);
+            }
+        }
+
+        legacy
+    }
+
+    /// Palette without tags and the legacy ids, the same for the unversioned layout and version 1
+    fn put_legacy_palette(world: &TurtleWorld, bytes: &mut BytesMut) {
+        bytes.put_u64_le(world.pallete.palette.len() as u64);
+        for block_name in &world.pallete.palette {
+            bytes.put_u64_le(block_name.len() as u64);
+            bytes.put_slice(block_name.as_bytes());
+        }
+    }
+
+    /// Layout used before the world format had a header
+    fn to_legacy_bytes(world: &TurtleWorld) -> Bytes {
+        let world = &to_legacy_ids(world);
+        let mut bytes = BytesMut::new();
+        put_legacy_palette(world, &mut bytes);
+
+        bytes.put_u64_le(world.data.chunks.len() as u64);
+        for (loc, chunk) in &world.data.chunks {
+            bytes.put_i32_le(loc.x);
+            bytes.put_i8(loc.y);
+            bytes.put_i32_le(loc.z);
+            bytes.put_u64_le(RealChunkShape::SIZE as u64 * 2);
+            for z in 0..16u32 {
+                for y in 0..16u32 {
+                    for x in 0..16u32 {
+                        bytes.put_u16_le(chunk.raw_voxel(&[x + 1, y + 1, z + 1]).id);
+                    }
+                }
+            }
+        }
+
+        bytes.freeze()
+    }
+
+    /// Tiny xorshift so the corruption tests are reproducible without extra dependencies
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
     #[test]
     fn test_encoding_and_decoding() {
         let mut world = TurtleWorld::new();
 
         let (palette, world_data) = world.get_fields_mut(); 
-        let _ = palette.get_pallete_index("hello world");
+        let (id, _) = palette.get_pallete_index("hello world");
         let loc = ChunkLocation::xyz(0, 0, 0);
         let mut chunk = TurtleChunk {
             location: loc.clone(),
-            data: [TurtleVoxel::air(); ChunkShape::SIZE as usize],
+            data: [TurtleVoxel::unknown(); ChunkShape::SIZE as usize],
         };
 
-        chunk.data[ChunkShape::linearize([15u32; 3]) as usize] = TurtleVoxel::id(12);
+        chunk.data[ChunkShape::linearize([15u32; 3]) as usize] = TurtleVoxel::id(id as u16);
         world_data.chunks.insert(loc, chunk);
 
         let bytes = world.to_bytes().expect("Cannot serialize!");
@@ -411,4 +926,227 @@ mod tests {
 
         assert!(deserialized == world);
     }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_legacy_migration() {
+        let world = test_world();
+
+        let migrated = TurtleWorld::from_bytes(to_legacy_bytes(&world)).expect("Cannot read legacy world");
+        assert!(migrated == world);
+
+        let bytes = migrated.to_bytes().expect("Cannot serialize!");
+        assert!(bytes.starts_with(b"TWLD"));
+        assert!(TurtleWorld::from_bytes(bytes).expect("Cannot deserialize") == world);
+    }
+
+    #[test]
+    fn test_version_1_migration() {
+        let mut world = test_world();
+        world.data.remove_global_block_by_xyz(3, 0, 5).expect("Cannot remove block");
+
+        //Version 1 had the same chunks, only the ids were different and the palette had no tags
+        let legacy = to_legacy_ids(&world);
+        let mut payload = BytesMut::new();
+        put_legacy_palette(&legacy, &mut payload);
+        payload.put_u64_le(legacy.data.chunks.len() as u64);
+        for chunk in legacy.data.chunks.values() {
+            write_chunk(chunk, &mut payload).expect("Cannot serialize chunk!");
+        }
+        let mut bytes = frame_payload(payload.freeze()).expect("Cannot frame payload!").to_vec();
+        bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+        let migrated = TurtleWorld::from_bytes(bytes.into()).expect("Cannot read version 1 world");
+
+        //Air could not be told apart from unknown blocks back then
+        assert!(migrated.get_voxel_by_global_xyz(3, 0, 5).is_some_and(|voxel| voxel.is_unknown()));
+        assert!(migrated.get_voxel_by_global_xyz(3, 1, 5).is_some_and(|voxel| voxel.is_solid()));
+        assert_eq!(migrated.pallete, world.pallete);
+        assert!(world.get_voxel_by_global_xyz(3, 0, 5).is_some_and(|voxel| voxel.is_air()));
+    }
+
+    #[test]
+    fn test_unknown_is_not_air() {
+        let mut world = TurtleWorld::new();
+        world.data.force_get_mut_chunk_by_loc(&ChunkLocation::xyz(0, 0, 0));
+        assert!(world.get_voxel_by_global_xyz(1, 2, 3).is_some_and(|voxel| voxel.is_unknown()));
+
+        world.data.remove_global_block_by_xyz(1, 2, 3).expect("Cannot mark block as air");
+        assert!(world.data.remove_global_block_by_xyz(1, 2, 3).is_err());
+
+        let deserialized = TurtleWorld::from_bytes(world.to_bytes().expect("Cannot serialize!")).expect("Cannot deserialize");
+        assert!(deserialized.get_voxel_by_global_xyz(1, 2, 3).is_some_and(|voxel| voxel.is_air()));
+        assert!(deserialized.get_voxel_by_global_xyz(1, 2, 4).is_some_and(|voxel| voxel.is_unknown()));
+    }
+
+    #[test]
+    fn test_run_length_is_smaller() {
+        let world = test_world();
+
+        let bytes = world.to_bytes().expect("Cannot serialize!");
+        assert!(bytes.len() * 10 < to_legacy_bytes(&world).len());
+    }
+
+    #[test]
+    fn test_truncated_input_is_an_error() {
+        let world = test_world();
+
+        for bytes in [world.to_bytes().expect("Cannot serialize!"), to_legacy_bytes(&world)] {
+            for len in 0..bytes.len() {
+                assert!(TurtleWorld::from_bytes(bytes.slice(..len)).is_err(), "Truncated to {len} bytes was accepted");
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupted_input_does_not_panic() {
+        let world = test_world();
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+
+        for bytes in [world.to_bytes().expect("Cannot serialize!"), to_legacy_bytes(&world)] {
+            for _ in 0..2000 {
+                let mut corrupted = bytes.to_vec();
+                for _ in 0..(1 + next_random(&mut state) % 4) {
+                    let i = (next_random(&mut state) % corrupted.len() as u64) as usize;
+                    corrupted[i] = next_random(&mut state) as u8;
+                }
+
+                let _ = TurtleWorld::from_bytes(corrupted.into());
+            }
+        }
+
+        //Checksum catches any change of the versioned format
+        let mut corrupted = world.to_bytes().expect("Cannot serialize!").to_vec();
+        let middle = corrupted.len() / 2;
+        corrupted[middle] ^= 0xFF;
+        assert!(TurtleWorld::from_bytes(corrupted.into()).is_err());
+
+        for len in [0usize, 1, 7, 64, 4096] {
+            let garbage: Vec<u8> = (0..len).map(|_| next_random(&mut state) as u8).collect();
+            let _ = TurtleWorld::from_bytes(garbage.into());
+        }
+    }
+
+    #[test]
+    fn test_region_encoding() {
+        let mut world = TurtleWorld::new();
+
+        let (palette, world_data) = world.get_fields_mut();
+        let _ = palette.get_pallete_index("minecraft:stone");
+        for loc in [ChunkLocation::xyz(0, 0, 0), ChunkLocation::xyz(1, -1, 0), ChunkLocation::xyz(5, 0, 5)] {
+            world_data.force_get_mut_chunk_by_loc(&loc);
+        }
+
+        let bytes = world
+            .region_to_bytes(&ChunkLocation::xyz(-1, -1, -1), &ChunkLocation::xyz(1, 1, 1))
+            .expect("Cannot serialize region!");
+        let region = TurtleWorld::from_bytes(bytes).expect("Cannot deserialize region");
+
+        let mut client_world = TurtleWorld::new();
+        let mut loaded = client_world.extend(region);
+        loaded.sort();
+
+        assert_eq!(loaded, vec![ChunkLocation::xyz(0, 0, 0), ChunkLocation::xyz(1, -1, 0)]);
+        assert_eq!(client_world.pallete, world.pallete);
+    }
+
+    #[test]
+    fn test_palette_tags() {
+        let mut world = test_world();
+        let furnace = "minecraft:furnace[facing=north,lit=false]";
+        let tags = vec!["minecraft:mineable/pickaxe".to_string()];
+
+        let (id, changed) = world.pallete.get_pallete_index_with_tags(furnace, &tags);
+        assert!(changed);
+        assert_eq!(world.pallete.get_pallete_index_with_tags(furnace, &tags), (id, false));
+        assert_eq!(world.pallete.get_pallete_index_with_tags(furnace, &[]), (id, true));
+        assert_eq!(world.pallete.get_pallete_index_with_tags(furnace, &tags), (id, true));
+        assert_eq!(split_palette_entry(furnace), ("minecraft:furnace", Some("facing=north,lit=false")));
+        assert_eq!(split_palette_entry("minecraft:stone"), ("minecraft:stone", None));
+
+        let deserialized = TurtleWorld::from_bytes(world.to_bytes().expect("Cannot serialize!")).expect("Cannot deserialize");
+        assert_eq!(deserialized.pallete.get_tags_from_id(id as u16), Some(&[tags[0].clone().into()][..]));
+        assert!(deserialized == world);
+
+        let mut loaded = TurtleWorld::new();
+        loaded.replace_palette_bytes(world.palette_to_bytes().expect("Cannot serialize palette!")).expect("Cannot read palette");
+        assert_eq!(loaded.pallete, world.pallete);
+    }
+
+    #[test]
+    fn test_parts_encoding() {
+        let world = test_world();
+
+        let palette = world.palette_to_bytes().expect("Cannot serialize palette!");
+        let chunks: Vec<Bytes> = [ChunkLocation::xyz(0, 0, 0), ChunkLocation::xyz(-3, 4, 7)]
+            .iter()
+            .map(|loc| world.chunk_to_bytes(loc).expect("Cannot serialize chunk!").expect("Chunk is missing"))
+            .collect();
+        assert!(world.chunk_to_bytes(&ChunkLocation::xyz(9, 9, 9)).expect("Cannot serialize chunk!").is_none());
+
+        let deserialized = TurtleWorld::from_parts(palette.clone(), chunks.clone()).expect("Cannot deserialize parts");
+        assert!(deserialized == world);
+
+        //A chunk is not a palette and the other way around
+        assert!(TurtleWorld::from_parts(chunks[0].clone(), chunks.clone()).is_err());
+        assert!(TurtleWorld::from_parts(palette.clone(), [palette]).is_err());
+    }
+
+    #[test]
+    fn test_dirty_chunks() {
+        let mut world = test_world();
+        assert_eq!(world.data.take_dirty(), vec![ChunkLocation::xyz(-3, 4, 7), ChunkLocation::xyz(0, 0, 0)]);
+        assert!(world.data.take_dirty().is_empty());
+
+        assert!(world.get_voxel_by_global_xyz(3, 0, 5).is_some_and(|voxel| voxel.is_solid()));
+        assert!(world.get_voxel_by_global_xyz(100, 0, 100).is_none());
+        assert!(world.data.take_dirty().is_empty());
+
+        world.data.remove_global_block_by_xyz(3, 0, 5).expect("Cannot remove block");
+        assert_eq!(world.data.take_dirty(), vec![ChunkLocation::xyz(0, 0, 0)]);
+
+        //Chunks written one by one replace the old ones without making them dirty
+        let mut loaded = TurtleWorld::new();
+        loaded.replace_palette_bytes(world.palette_to_bytes().expect("Cannot serialize palette!")).expect("Cannot read palette");
+        let chunk = world.chunk_to_bytes(&ChunkLocation::xyz(0, 0, 0)).expect("Cannot serialize chunk!").expect("Chunk is missing");
+        assert_eq!(loaded.insert_chunk_bytes(chunk).expect("Cannot read chunk"), ChunkLocation::xyz(0, 0, 0));
+        assert!(loaded.get_voxel_by_global_xyz(3, 0, 5).is_some_and(|voxel| voxel.is_air()));
+        assert!(loaded.data.take_dirty().is_empty());
+
+        //Palette cannot shrink under chunks that use it
+        assert!(loaded.replace_palette_bytes(TurtleWorld::new().palette_to_bytes().expect("Cannot serialize palette!")).is_err());
+    }
+
+    #[test]
+    fn test_palette_entries() {
+        let mut world = test_world();
+        let mut loaded = TurtleWorld::new();
+        loaded.replace_palette_bytes(world.palette_to_bytes().expect("Cannot serialize palette!")).expect("Cannot read palette");
+        let (stone, dirt) = (world.pallete.palette_hashmap["minecraft:stone"], world.pallete.palette_hashmap["minecraft:dirt"]);
+        assert_eq!(world.pallete.take_dirty(), vec![stone, dirt]);
+        assert!(world.pallete.take_dirty().is_empty());
+
+        let tags = vec!["minecraft:mineable/pickaxe".to_string()];
+        let (furnace, _) = world.pallete.get_pallete_index_with_tags("minecraft:furnace", &tags);
+        assert_eq!(world.pallete.get_pallete_index_with_tags("minecraft:stone", &tags), (stone, true));
+        let dirty = world.pallete.take_dirty();
+        assert_eq!(dirty, vec![stone, furnace]);
+
+        let entries = world.palette_entries_to_bytes(&dirty).expect("Cannot serialize palette entries!");
+        loaded.apply_palette_entries_bytes(entries.clone()).expect("Cannot read palette entries");
+        assert_eq!(loaded.pallete, world.pallete);
+        assert!(loaded.pallete.take_dirty().is_empty());
+
+        //Entries after the end of the palette and other names under a known id are rejected
+        assert!(TurtleWorld::new().apply_palette_entries_bytes(entries).is_err());
+        let mut other = TurtleWorld::new();
+        let (sand, _) = other.pallete.get_pallete_index("minecraft:sand");
+        assert_eq!(sand, stone);
+        let renamed = other.palette_entries_to_bytes(&[sand]).expect("Cannot serialize palette entries!");
+        assert!(loaded.apply_palette_entries_bytes(renamed).is_err());
+        assert!(world.palette_entries_to_bytes(&[100]).is_err());
+    }
 }
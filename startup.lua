@@ -1,23 +1,79 @@
-local function unsafe_exec(func)
-  local ok_e, err_e = func()
-  if ok_e then
-    return tostring(ok_e)
-  else  
-    return tostring(err_e)
+-- WebTurtle client, the backend serves it on /client.lua:
+-- wget http://<backend>/client.lua startup.lua
+local PROTOCOL_VERSION = 1
+-- Filled in by the backend from its public_url (or the host the client was downloaded from)
+local URL = ""
+-- Has to match auth.turtle_secret of the backend, filled in when downloaded with an operator token
+local SECRET = ""
+local RECONNECT_DELAY = 5
+
+-- Runs { id = number, lua = string } and returns { id, ok, values } or { id, ok = false, error }
+local function handle(message)
+  local request = textutils.unserialiseJSON(message)
+  if type(request) ~= "table" or type(request.lua) ~= "string" then
+    return { id = textutils.json_null, ok = false, error = "Invalid request" }
+  end
+
+  local func, load_err = load(request.lua, "=web_turtle", "t", _ENV)
+  if not func then
+    return { id = request.id, ok = false, error = tostring(load_err) }
+  end
+
+  local result = table.pack(pcall(func))
+  if not result[1] then
+    return { id = request.id, ok = false, error = tostring(result[2]) }
   end
-end  
-
-local ws, err = http.websocket("ws://127.0.0.1:8080/turtle/")
-if err then
-  print(err)
-else if ws then
-  print("> CONNECTED")
-  while true do
-    local message = ws.receive()
-    local func = load(message)
-    local ok, retval = pcall(unsafe_exec, func)
-    local result = tostring(retval)
-    ws.send(result)
+
+  -- nil would leave a hole in the array
+  local values = textutils.empty_json_array
+  if result.n > 1 then
+    values = {}
+    for i = 2, result.n do
+      if result[i] == nil then
+        values[i - 1] = textutils.json_null
+      else
+        values[i - 1] = result[i]
+      end
+    end
+  end
+
+  return { id = request.id, ok = true, values = values }
+end
+
+local function encode(response)
+  local ok, json = pcall(textutils.serialiseJSON, response)
+  if ok then
+    return json
   end
+
+  return textutils.serialiseJSON({ id = response.id, ok = false, error = "Cannot serialise the result: " .. tostring(json) })
+end
+
+if URL == "" then
+  error("Download the client from the backend: wget http://<backend>/client.lua startup.lua")
+end
+
+while true do
+  local ws, err = http.websocket(URL)
+  if not ws then
+    print(err)
+  else
+    print("> CONNECTED")
+    ws.send(textutils.serialiseJSON({ version = PROTOCOL_VERSION, secret = SECRET }))
+
+    -- A closed socket throws, the turtle then reconnects
+    while true do
+      local ok, message = pcall(ws.receive)
+      if not ok or not message then
+        break
+      end
+      if not pcall(ws.send, encode(handle(message))) then
+        break
+      end
+    end
+
+    print("> DISCONNECTED")
+  end
+
+  sleep(RECONNECT_DELAY)
 end
-end 